@@ -1,5 +1,5 @@
 use {
-    client_api::{external::{leptos::{IntoView, View, view}, types::external::serde::Deserialize}, plugin::{PluginData, PluginEventData, PluginTrait}, style::Style}, std::path::PathBuf,
+    client_api::{external::{leptos::{create_signal, IntoView, SignalGet, SignalSet, View, view}, types::external::serde::Deserialize}, plugin::{PluginData, PluginEventData, PluginTrait}, style::Style},
     client_api::result::EventResult,
     client_api::api
 };
@@ -7,7 +7,47 @@ use {
 #[derive(Clone, Debug, Deserialize)]
 pub struct SignedMedia {
     path: String,
-    signature: String
+    signature: String,
+    kind: Option<MediaKind>,
+    thumbnail_signature: Option<String>,
+    #[serde(default)]
+    thumbnail_sizes: Vec<u32>,
+    poster_signature: Option<String>,
+    preview_signature: Option<String>,
+    #[serde(default)]
+    width: Option<u32>,
+    #[serde(default)]
+    height: Option<u32>,
+    #[serde(default)]
+    duration: Option<u64>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    alternates: Vec<AlternateMedia>,
+    #[serde(default)]
+    group: Option<GroupKind>,
+    #[serde(default)]
+    members: Vec<SignedMedia>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AlternateMedia {
+    location_name: String
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupKind {
+    LivePhoto,
+    Burst
 }
 
 pub struct Plugin {}
@@ -24,29 +64,97 @@ impl PluginTrait for Plugin {
 
     fn get_component(&self, data: PluginEventData) -> EventResult<Box<dyn FnOnce() -> View>> {
         let media = data.get_data::<SignedMedia>()?;
-        let path = PathBuf::from(media.path);
-        let extension = path.extension().unwrap().to_str().unwrap().to_lowercase().to_string();
-        let path_string = path.as_os_str().to_str().unwrap().to_string();
-        let path_encoded = api::encode_url_component(&path_string);
-        let signature_encoded = api::encode_url_component(&media.signature);
-        let url = api::relative_url("/api/plugin/timeline_plugin_media_scan/file/").unwrap().join(&format!("{}/{}", &path_encoded, &signature_encoded)).unwrap().as_str().to_string();
+        let kind = media.kind.unwrap_or(MediaKind::Image);
+        let url = plugin_url("file", &media.path, &media.signature);
+        // The motion of a Live Photo plays while the pointer rests on its still.
+        let motion = match media.group {
+            Some(GroupKind::LivePhoto) => media.members.iter().find(|v| v.kind == Some(MediaKind::Video)).map(|video| match &video.preview_signature {
+                Some(signature) => plugin_url("preview", &video.path, signature),
+                None => plugin_url("file", &video.path, &video.signature)
+            }),
+            _ => None
+        };
+        // The other shots of a burst are listed below the first one, each linking to its file.
+        let burst: Vec<(String, String)> = match media.group {
+            Some(GroupKind::Burst) => media.members.iter().map(|v| {
+                let url = plugin_url("file", &v.path, &v.signature);
+                let preview = match (&v.thumbnail_signature, v.thumbnail_sizes.first()) {
+                    (Some(signature), Some(size)) => thumbnail_url(*size, &v.path, signature),
+                    _ => url.clone()
+                };
+                (url, preview)
+            }).collect(),
+            _ => Vec::new()
+        };
+        let burst_size = match burst.is_empty() {
+            true => 0,
+            false => burst.len() + 1
+        };
+        let thumbnails: Vec<(u32, String)> = match &media.thumbnail_signature {
+            Some(signature) => media.thumbnail_sizes.iter().map(|size| (*size, thumbnail_url(*size, &media.path, signature))).collect(),
+            None => Vec::new()
+        };
+        let poster = media.poster_signature.map(|signature| plugin_url("poster", &media.path, &signature));
+        let preview = media.preview_signature.map(|signature| plugin_url("preview", &media.path, &signature));
+        // Known dimensions reserve the space of the media before anything is loaded.
+        let aspect_ratio = match (media.width, media.height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => Some(format!("{} / {}", width, height)),
+            _ => None
+        };
+        let duration = media.duration.map(format_duration);
+        let description = media.description;
+        // Copies of the same file in other locations are collapsed into this event.
+        let mut also_in: Vec<String> = media.alternates.into_iter().map(|v| v.location_name).collect();
+        also_in.sort();
+        also_in.dedup();
+        let also_in = also_in.join(", ");
         Ok(Box::new(move || {
             view! {
-                {match extension.as_str() {
-                    "mp4" | "mkv" | "webm" | "mov" => {
+                {match kind {
+                    MediaKind::Video => {
+                        // A preview loops silently until it is clicked, then the full video takes its place.
+                        let has_preview = preview.is_some();
+                        let (full, set_full) = create_signal(!has_preview);
                         view! {
-                            <video
-                                style:width="100%"
-                                style:color="var(--lightColor)"
-                                src=url
-                                controls
-                            >
-                                Loading video.
-                            </video>
+                            {move || match (full.get(), preview.clone()) {
+                                (false, Some(preview)) => view! {
+                                    <video
+                                        style:width="100%"
+                                        style:aspect-ratio=aspect_ratio.clone()
+                                        style:cursor="pointer"
+                                        src=preview
+                                        poster=poster.clone()
+                                        prop:muted=true
+                                        muted
+                                        autoplay
+                                        loop
+                                        playsinline
+                                        on:click=move |_| set_full.set(true)
+                                    >
+                                    </video>
+                                }
+                                    .into_view(),
+                                _ => view! {
+                                    <video
+                                        style:width="100%"
+                                        style:aspect-ratio=aspect_ratio.clone()
+                                        style:color="var(--lightColor)"
+                                        src=url.clone()
+                                        poster=poster.clone()
+                                        preload="none"
+                                        autoplay=has_preview
+                                        controls
+                                    >
+                                        Loading video.
+                                    </video>
+                                }
+                                    .into_view(),
+                            }}
+                            {duration.map(|duration| view! { <div style:color="var(--lightColor)">{duration}</div> })}
                         }
                             .into_view()
                     }
-                    "mp3" | "opus" | "m4a" => {
+                    MediaKind::Audio => {
                         view! {
                             <audio
                                 style:width="100%"
@@ -56,11 +164,77 @@ impl PluginTrait for Plugin {
                             >
                                 Loading audio
                             </audio>
+                            {duration.map(|duration| view! { <div style:color="var(--lightColor)">{duration}</div> })}
                         }
                             .into_view()
                     }
-                    _ => view! { <img style:width="100%" src=url /> }.into_view(),
+                    MediaKind::Image => {
+                        let image = match thumbnails.last() {
+                            // The browser picks the smallest thumbnail that is sharp enough, the original is one click away.
+                            Some((_, largest)) => {
+                                let srcset = thumbnails.iter().map(|(size, url)| format!("{} {}w", url, size)).collect::<Vec<_>>().join(", ");
+                                view! {
+                                    <a href=url target="_blank">
+                                        <img style:width="100%" style:aspect-ratio=aspect_ratio src=largest.clone() srcset=srcset sizes="100vw" />
+                                    </a>
+                                }.into_view()
+                            }
+                            None => view! { <img style:width="100%" style:aspect-ratio=aspect_ratio src=url /> }.into_view(),
+                        };
+                        match motion {
+                            Some(motion) => {
+                                let (playing, set_playing) = create_signal(false);
+                                view! {
+                                    <div
+                                        style:position="relative"
+                                        on:mouseenter=move |_| set_playing.set(true)
+                                        on:mouseleave=move |_| set_playing.set(false)
+                                    >
+                                        {image}
+                                        {move || playing.get().then(|| view! {
+                                            <video
+                                                style:position="absolute"
+                                                style:top="0"
+                                                style:left="0"
+                                                style:width="100%"
+                                                style:height="100%"
+                                                style:object-fit="cover"
+                                                style:pointer-events="none"
+                                                src=motion.clone()
+                                                prop:muted=true
+                                                muted
+                                                autoplay
+                                                loop
+                                                playsinline
+                                            >
+                                            </video>
+                                        })}
+                                    </div>
+                                }.into_view()
+                            }
+                            None => image,
+                        }
+                    }
                 }}
+                {description.map(|description| view! { <div>{description}</div> })}
+                {(burst_size > 0).then(|| {
+                    let (expanded, set_expanded) = create_signal(false);
+                    view! {
+                        <div style:color="var(--lightColor)" style:cursor="pointer" on:click=move |_| set_expanded.set(!expanded.get())>
+                            "Burst of " {burst_size} " photos"
+                        </div>
+                        {move || expanded.get().then(|| view! {
+                            <div style:display="flex" style:flex-wrap="wrap" style:gap="4px">
+                                {burst.iter().map(|(url, preview)| view! {
+                                    <a href=url.clone() target="_blank">
+                                        <img style:height="64px" src=preview.clone() loading="lazy" />
+                                    </a>
+                                }).collect::<Vec<_>>()}
+                            </div>
+                        })}
+                    }
+                })}
+                {(!also_in.is_empty()).then(|| view! { <div style:color="var(--lightColor)">"Also in " {also_in}</div> })}
             }.into_view()
         }))
     }
@@ -69,3 +243,23 @@ impl PluginTrait for Plugin {
         Style::Acc1
     }
 }
+
+/// A signed URL of one of the routes of the server plugin.
+fn plugin_url(route: &str, path: &str, signature: &str) -> String {
+    api::relative_url(&format!("/api/plugin/timeline_plugin_media_scan/{}/", route)).unwrap().join(&format!("{}/{}", api::encode_url_component(path), api::encode_url_component(signature))).unwrap().as_str().to_string()
+}
+
+/// A signed URL of a thumbnail that fits into `size` pixels.
+fn thumbnail_url(size: u32, path: &str, signature: &str) -> String {
+    // The path is already encoded by the server in a way that survives non UTF-8 file names.
+    api::relative_url("/api/plugin/timeline_plugin_media_scan/thumb/").unwrap().join(&format!("{}/{}/{}", size, api::encode_url_component(path), api::encode_url_component(signature))).unwrap().as_str().to_string()
+}
+
+/// Formats milliseconds as `m:ss` or `h:mm:ss`.
+fn format_duration(milliseconds: u64) -> String {
+    let seconds = milliseconds / 1000;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
@@ -0,0 +1,1092 @@
+use {
+    crate::{
+        geo, groups::CaptureIdentifiers, media_type::MetadataFormat, CaptureTime, FileMetadata,
+        MediaDetails, TimeSource,
+    },
+    server_api::external::types::external::chrono::{
+        DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc,
+    },
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::Path,
+    },
+};
+
+// Seconds between 1904-01-01 (QuickTime epoch) and 1970-01-01.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+// Seconds between 1970-01-01 and 2001-01-01 (Matroska epoch).
+const MATROSKA_EPOCH_OFFSET: i64 = 978_307_200;
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_HEADER_SCAN: u64 = 1024 * 1024;
+// By the sampling frequency index of an ADTS header.
+const ADTS_SAMPLE_RATES: [u64; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+pub fn read_container_metadata(path: &Path, format: MetadataFormat) -> Option<FileMetadata> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    match format {
+        MetadataFormat::IsoBmff => read_isobmff(&mut reader),
+        MetadataFormat::Matroska => read_matroska(&mut reader),
+        MetadataFormat::Id3 => read_mp3(&mut reader),
+        MetadataFormat::Adts => read_adts(&mut reader),
+        MetadataFormat::Ogg => read_ogg(&mut reader),
+        MetadataFormat::Flac => read_flac(&mut reader),
+        MetadataFormat::Riff => read_riff(&mut reader),
+        MetadataFormat::Exif | MetadataFormat::Unsupported => None,
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Option<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Option<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u64::from_be_bytes(buf))
+}
+
+/// Skips `size` bytes. Sizes that do not fit a seek offset and seeks that do not end where they
+/// should fail, so a corrupt size can not send the reader back to data it already read.
+fn skip<R: Seek>(reader: &mut R, size: u64) -> Option<()> {
+    let start = reader.stream_position().ok()?;
+    let end = reader
+        .seek(SeekFrom::Current(i64::try_from(size).ok()?))
+        .ok()?;
+    (Some(end) == start.checked_add(size)).then_some(())
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn duration_ms(units: u64, units_per_second: u64) -> Option<u64> {
+    match units_per_second {
+        0 => None,
+        v => Some((units as u128 * 1000 / v as u128) as u64),
+    }
+}
+
+/// Parses textual timestamps as found in QuickTime, ID3, Vorbis comment and XMP tags. Values without
+/// a time of day (e.g. a release year) are rejected, values without an offset are interpreted in
+/// the timezone of the server.
+pub fn parse_text_time(text: &str) -> Option<CaptureTime> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if let Ok(v) = DateTime::parse_from_rfc3339(text) {
+        return Some(CaptureTime {
+            time: v.with_timezone(&Utc),
+            source: TimeSource::Container,
+        });
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(v) = DateTime::parse_from_str(text, format) {
+            return Some(CaptureTime {
+                time: v.with_timezone(&Utc),
+                source: TimeSource::Container,
+            });
+        }
+    }
+    let text = text.trim_end_matches('Z');
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(v) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(local_capture_time(v));
+        }
+    }
+    None
+}
+
+fn local_capture_time(naive: NaiveDateTime) -> CaptureTime {
+    CaptureTime {
+        time: Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|v| v.with_timezone(&Utc))
+            .unwrap_or_else(|| DateTime::from_naive_utc_and_offset(naive, Utc)),
+        source: TimeSource::ContainerLocalTime,
+    }
+}
+
+// ISO base media file format (mp4, mov, m4a)
+
+fn read_isobmff<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    let moov = loop {
+        let size = read_u32(reader)? as u64;
+        let mut kind = [0; 4];
+        reader.read_exact(&mut kind).ok()?;
+        let (body_size, to_end) = match size {
+            0 => (0, true),
+            1 => (read_u64(reader)?.checked_sub(16)?, false),
+            v => (v.checked_sub(8)?, false),
+        };
+        if &kind == b"moov" {
+            let mut moov = Vec::new();
+            match to_end {
+                true => reader.take(MAX_MOOV_SIZE).read_to_end(&mut moov).ok()?,
+                false if body_size <= MAX_MOOV_SIZE => {
+                    reader.take(body_size).read_to_end(&mut moov).ok()?
+                }
+                false => return None,
+            };
+            break moov;
+        }
+        if to_end {
+            return None;
+        }
+        skip(reader, body_size)?;
+    };
+
+    let mut mvhd_time = None;
+    let mut details = MediaDetails::default();
+    let mut audio_codec = None;
+    let mut tags = HashMap::new();
+    let mut udta_position = None;
+    for (kind, body) in Boxes::new(&moov) {
+        match &kind {
+            b"mvhd" => {
+                mvhd_time = parse_mvhd(body);
+                details.duration = parse_mvhd_duration(body);
+            }
+            b"trak" => match parse_trak(body) {
+                Some(Track::Video {
+                    codec,
+                    width,
+                    height,
+                    orientation,
+                }) if details.codec.is_none() => {
+                    details.codec = codec;
+                    details.width = Some(width);
+                    details.height = Some(height);
+                    details.orientation = orientation;
+                }
+                Some(Track::Audio { codec }) if audio_codec.is_none() => audio_codec = codec,
+                _ => {}
+            },
+            b"meta" => parse_mdta_meta(body, &mut tags),
+            b"udta" => {
+                for (kind, body) in Boxes::new(body) {
+                    match &kind {
+                        b"meta" => parse_mdta_meta(body, &mut tags),
+                        // A 16 bit length and a language code precede the text.
+                        b"\xA9xyz" => {
+                            if let Some(text) = body.get(4..) {
+                                udta_position = geo::parse_iso6709(&String::from_utf8_lossy(text));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Files without a video track are described by their audio codec.
+    details.codec = details.codec.or(audio_codec);
+    Some(FileMetadata {
+        creation_time: tags
+            .get("com.apple.quicktime.creationdate")
+            .and_then(|v| parse_text_time(v))
+            .or(mvhd_time),
+        details,
+        position: tags
+            .get("com.apple.quicktime.location.ISO6709")
+            .and_then(|v| geo::parse_iso6709(v))
+            .or(udta_position),
+        identifiers: CaptureIdentifiers {
+            content_identifier: tags.remove("com.apple.quicktime.content.identifier"),
+            burst_id: None,
+        },
+        ..Default::default()
+    })
+}
+
+enum Track {
+    Video {
+        codec: Option<String>,
+        width: u32,
+        height: u32,
+        orientation: Option<u16>,
+    },
+    Audio {
+        codec: Option<String>,
+    },
+}
+
+fn parse_trak(body: &[u8]) -> Option<Track> {
+    let mut tkhd = None;
+    let mut handler = None;
+    let mut codec = None;
+    for (kind, body) in Boxes::new(body) {
+        match &kind {
+            b"tkhd" => tkhd = Some(body),
+            b"mdia" => {
+                for (kind, body) in Boxes::new(body) {
+                    match &kind {
+                        b"hdlr" => handler = body.get(8..12),
+                        b"minf" => codec = parse_minf_codec(body),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    match handler? {
+        b"vide" => {
+            let tkhd = tkhd?;
+            let matrix = match tkhd.first()? {
+                1 => 52,
+                _ => 40,
+            };
+            // The presentation size is a 16.16 fixed point number after the matrix.
+            Some(Track::Video {
+                codec,
+                width: be_u32(tkhd, matrix + 36)? >> 16,
+                height: be_u32(tkhd, matrix + 40)? >> 16,
+                orientation: matrix_orientation(tkhd.get(matrix..matrix + 36)?),
+            })
+        }
+        b"soun" => Some(Track::Audio { codec }),
+        _ => None,
+    }
+}
+
+/// The sample entry format of the first sample description, e.g. `avc1`, `hvc1` or `mp4a`.
+fn parse_minf_codec(minf: &[u8]) -> Option<String> {
+    let (_, stbl) = Boxes::new(minf).find(|(kind, _)| kind == b"stbl")?;
+    let (_, stsd) = Boxes::new(stbl).find(|(kind, _)| kind == b"stsd")?;
+    let format = stsd.get(12..16)?;
+    Some(String::from_utf8_lossy(format).trim().to_string())
+}
+
+/// Maps the rotation of a track matrix to the matching EXIF orientation.
+fn matrix_orientation(matrix: &[u8]) -> Option<u16> {
+    let a = be_u32(matrix, 0)? as i32;
+    let b = be_u32(matrix, 4)? as i32;
+    let c = be_u32(matrix, 12)? as i32;
+    let d = be_u32(matrix, 16)? as i32;
+    const ONE: i32 = 0x10000;
+    match (a, b, c, d) {
+        (ONE, 0, 0, ONE) => Some(1),
+        (0, ONE, v, 0) if v == -ONE => Some(6),
+        (v, 0, 0, w) if v == -ONE && w == -ONE => Some(3),
+        (0, v, ONE, 0) if v == -ONE => Some(8),
+        _ => None,
+    }
+}
+
+fn parse_mvhd_duration(body: &[u8]) -> Option<u64> {
+    let (timescale, duration) = match body.first()? {
+        1 => (be_u32(body, 20)?, be_u64(body, 24)?),
+        _ => (be_u32(body, 12)?, be_u32(body, 16)? as u64),
+    };
+    duration_ms(duration, timescale as u64)
+}
+
+struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Boxes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Boxes { data }
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = be_u32(self.data, 0)? as usize;
+        let kind: [u8; 4] = self.data.get(4..8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, self.data.len()),
+            1 => (16, be_u64(self.data, 8)? as usize),
+            v => (8, v),
+        };
+        if size < header || size > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let body = &self.data[header..size];
+        self.data = &self.data[size..];
+        Some((kind, body))
+    }
+}
+
+fn parse_mvhd(body: &[u8]) -> Option<CaptureTime> {
+    let seconds = match body.first()? {
+        1 => i64::try_from(be_u64(body, 4)?).ok()?,
+        _ => be_u32(body, 4)? as i64,
+    };
+    if seconds == 0 {
+        return None;
+    }
+    Some(CaptureTime {
+        time: DateTime::from_timestamp(seconds.checked_sub(QUICKTIME_EPOCH_OFFSET)?, 0)?,
+        source: TimeSource::Container,
+    })
+}
+
+/// Collects the string values of a QuickTime `mdta` metadata box (`keys` + `ilst`).
+fn parse_mdta_meta(body: &[u8], tags: &mut HashMap<String, String>) {
+    // ISO meta boxes carry a version/flags header, QuickTime ones start with the children directly.
+    let body = match body.get(4..8) {
+        Some(b"hdlr") | Some(b"keys") | Some(b"ilst") => body,
+        _ => body.get(4..).unwrap_or_default(),
+    };
+    let mut keys = Vec::new();
+    let mut items = None;
+    for (kind, body) in Boxes::new(body) {
+        match &kind {
+            b"keys" => {
+                let count = be_u32(body, 4).unwrap_or(0);
+                let mut at = 8;
+                for _ in 0..count {
+                    let Some(size) = be_u32(body, at).map(|v| v as usize) else {
+                        break;
+                    };
+                    let Some(name) = body.get(at + 8..at + size.max(8)) else {
+                        break;
+                    };
+                    keys.push(String::from_utf8_lossy(name).to_string());
+                    at += size.max(8);
+                }
+            }
+            b"ilst" => items = Some(body),
+            _ => {}
+        }
+    }
+    let Some(items) = items else {
+        return;
+    };
+    for (kind, body) in Boxes::new(items) {
+        let index = u32::from_be_bytes(kind) as usize;
+        let Some(key) = index.checked_sub(1).and_then(|v| keys.get(v)) else {
+            continue;
+        };
+        for (kind, data) in Boxes::new(body) {
+            // Type indicator 1 is UTF-8 text, the value follows the type and locale fields.
+            if &kind == b"data" && be_u32(data, 0) == Some(1) {
+                if let Some(value) = data.get(8..) {
+                    tags.insert(key.clone(), String::from_utf8_lossy(value).to_string());
+                }
+            }
+        }
+    }
+}
+
+// Matroska / WebM
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const EBML_SEGMENT: u32 = 0x18538067;
+const EBML_INFO: u32 = 0x1549A966;
+const EBML_DATE_UTC: u32 = 0x4461;
+const EBML_TIMECODE_SCALE: u32 = 0x2AD7B1;
+const EBML_DURATION: u32 = 0x4489;
+const EBML_TRACKS: u32 = 0x1654AE6B;
+const EBML_TRACK_ENTRY: u32 = 0xAE;
+const EBML_TRACK_TYPE: u32 = 0x83;
+const EBML_CODEC_ID: u32 = 0x86;
+const EBML_VIDEO: u32 = 0xE0;
+const EBML_PIXEL_WIDTH: u32 = 0xB0;
+const EBML_PIXEL_HEIGHT: u32 = 0xBA;
+const EBML_CLUSTER: u32 = 0x1F43B675;
+
+fn read_vint<R: Read>(reader: &mut R, keep_marker: bool) -> Option<(u64, bool)> {
+    let mut first = [0; 1];
+    reader.read_exact(&mut first).ok()?;
+    let length = first[0].leading_zeros() as usize + 1;
+    if length > 8 {
+        return None;
+    }
+    let mask = (0xFF_u16 >> length) as u8;
+    let mut value = match keep_marker {
+        true => first[0] as u64,
+        false => (first[0] & mask) as u64,
+    };
+    let mut all_ones = first[0] & mask == mask;
+    for _ in 1..length {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte).ok()?;
+        all_ones &= byte[0] == 0xFF;
+        value = (value << 8) | byte[0] as u64;
+    }
+    Some((value, all_ones && !keep_marker))
+}
+
+fn read_element_header<R: Read>(reader: &mut R) -> Option<(u32, Option<u64>)> {
+    let (id, _) = read_vint(reader, true)?;
+    let (size, unknown) = read_vint(reader, false)?;
+    Some((id as u32, (!unknown).then_some(size)))
+}
+
+fn read_matroska<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    let (id, size) = read_element_header(reader)?;
+    if id != EBML_HEADER {
+        return None;
+    }
+    skip(reader, size?)?;
+    let (id, _) = read_element_header(reader)?;
+    if id != EBML_SEGMENT {
+        return None;
+    }
+
+    let mut metadata = FileMetadata::default();
+    let start = reader.stream_position().ok()?;
+    let (mut info_read, mut tracks_read) = (false, false);
+    while !(info_read && tracks_read) && reader.stream_position().ok()? - start < MAX_HEADER_SCAN {
+        let Some((id, Some(size))) = read_element_header(reader) else {
+            break;
+        };
+        match id {
+            EBML_INFO | EBML_TRACKS => {
+                let mut body = Vec::new();
+                reader.take(size).read_to_end(&mut body).ok()?;
+                if id == EBML_INFO {
+                    parse_matroska_info(&body, &mut metadata);
+                    info_read = true;
+                } else {
+                    parse_matroska_tracks(&body, &mut metadata.details);
+                    tracks_read = true;
+                }
+            }
+            EBML_CLUSTER => break,
+            _ => {
+                skip(reader, size)?;
+            }
+        }
+    }
+    Some(metadata)
+}
+
+/// The children of a master element as id and body.
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let (id, size) = read_element_header(&mut data)?;
+        let size = size? as usize;
+        let body = data.get(..size)?;
+        data = &data[size..];
+        Some((id, body))
+    })
+}
+
+fn ebml_uint(body: &[u8]) -> Option<u64> {
+    match body.len() {
+        1..=8 => Some(body.iter().fold(0, |acc, v| (acc << 8) | *v as u64)),
+        _ => None,
+    }
+}
+
+fn ebml_float(body: &[u8]) -> Option<f64> {
+    match body.len() {
+        4 => Some(f32::from_be_bytes(body.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(body.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn parse_matroska_info(info: &[u8], metadata: &mut FileMetadata) {
+    let mut timecode_scale = 1_000_000;
+    let mut duration = None;
+    for (id, body) in ebml_elements(info) {
+        match id {
+            EBML_DATE_UTC if body.len() == 8 => {
+                let Some(nanoseconds) = be_u64(body, 0) else {
+                    continue;
+                };
+                metadata.creation_time = Some(CaptureTime {
+                    time: DateTime::from_timestamp_nanos(nanoseconds as i64)
+                        + Duration::seconds(MATROSKA_EPOCH_OFFSET),
+                    source: TimeSource::Container,
+                });
+            }
+            EBML_TIMECODE_SCALE => timecode_scale = ebml_uint(body).unwrap_or(timecode_scale),
+            EBML_DURATION => duration = ebml_float(body),
+            _ => {}
+        }
+    }
+    // The duration is counted in timecode scale units of nanoseconds.
+    metadata.details.duration = duration
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .map(|v| (v * timecode_scale as f64 / 1_000_000.0) as u64);
+}
+
+fn parse_matroska_tracks(tracks: &[u8], details: &mut MediaDetails) {
+    let mut audio_codec = None;
+    for (id, entry) in ebml_elements(tracks) {
+        if id != EBML_TRACK_ENTRY {
+            continue;
+        }
+        let (mut kind, mut codec, mut size) = (None, None, (None, None));
+        for (id, body) in ebml_elements(entry) {
+            match id {
+                EBML_TRACK_TYPE => kind = ebml_uint(body),
+                EBML_CODEC_ID => {
+                    codec = Some(
+                        String::from_utf8_lossy(body)
+                            .trim_end_matches('\0')
+                            .to_string(),
+                    )
+                }
+                EBML_VIDEO => {
+                    for (id, body) in ebml_elements(body) {
+                        match id {
+                            EBML_PIXEL_WIDTH => size.0 = ebml_uint(body),
+                            EBML_PIXEL_HEIGHT => size.1 = ebml_uint(body),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Track type 1 is video, 2 is audio.
+        match kind {
+            Some(1) if details.codec.is_none() => {
+                details.codec = codec;
+                details.width = size.0.map(|v| v as u32);
+                details.height = size.1.map(|v| v as u32);
+            }
+            Some(2) if audio_codec.is_none() => audio_codec = codec,
+            _ => {}
+        }
+    }
+    details.codec = details.codec.take().or(audio_codec);
+}
+
+// ID3v2 (mp3)
+
+fn read_mp3<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    let creation_time = read_id3(reader);
+    // Without a tag the audio starts at the beginning of the file.
+    if creation_time.is_none() {
+        reader.seek(SeekFrom::Start(0)).ok()?;
+    }
+    let mut details = MediaDetails::default();
+    if let Some(duration) = read_mpeg_audio_duration(reader) {
+        details.duration = Some(duration);
+        details.codec = Some("mp3".to_string());
+    }
+    Some(FileMetadata {
+        creation_time: creation_time.flatten(),
+        details,
+        ..Default::default()
+    })
+}
+
+/// Reads an ID3v2 tag at the start of the file and leaves the reader after it. Returns `None`
+/// if there is no tag.
+fn read_id3<R: Read>(reader: &mut R) -> Option<Option<CaptureTime>> {
+    let mut header = [0; 10];
+    reader.read_exact(&mut header).ok()?;
+    if &header[0..3] != b"ID3" {
+        return None;
+    }
+    let version = header[3];
+    let size = syncsafe(&header[6..10]) as u64;
+    let mut tag = Vec::new();
+    reader.take(size).read_to_end(&mut tag).ok()?;
+
+    let mut at = 0;
+    if header[5] & 0x40 != 0 {
+        at = match version {
+            4 => syncsafe(tag.get(0..4)?) as usize,
+            _ => be_u32(&tag, 0)? as usize + 4,
+        };
+    }
+
+    let mut frames = HashMap::new();
+    while at + 10 <= tag.len() && tag[at] != 0 {
+        let id = String::from_utf8_lossy(&tag[at..at + 4]).to_string();
+        let size = match version {
+            4 => syncsafe(&tag[at + 4..at + 8]) as usize,
+            _ => be_u32(&tag, at + 4)? as usize,
+        };
+        let Some(body) = tag.get(at + 10..at + 10 + size) else {
+            break;
+        };
+        if id.starts_with('T') {
+            frames.insert(id, decode_id3_text(body));
+        }
+        at += 10 + size;
+    }
+
+    Some(match frames.get("TDRC") {
+        Some(v) => parse_text_time(v),
+        None => parse_id3v23_time(&frames),
+    })
+}
+
+/// The duration of MPEG layer III audio from the frame count of a Xing/Info header or, for
+/// files without one, estimated from the bitrate of the first frame.
+fn read_mpeg_audio_duration<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let start = reader.stream_position().ok()?;
+    let mut data = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_HEADER_SCAN)
+        .read_to_end(&mut data)
+        .ok()?;
+    let end = reader.seek(SeekFrom::End(0)).ok()?;
+    let (at, header) = (0..data.len().saturating_sub(4)).find_map(|at| {
+        let header = be_u32(&data, at)?;
+        // Frame sync and layer III.
+        (header >> 21 == 0x7FF && (header >> 17) & 0b11 == 0b01).then_some((at, header))
+    })?;
+    let version = (header >> 19) & 0b11;
+    let mpeg1 = version == 0b11;
+    let bitrate_kbps: [u32; 16] = match mpeg1 {
+        true => [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+        ],
+        false => [
+            0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+        ],
+    };
+    let sample_rate = match ((header >> 10) & 0b11, version) {
+        (0, 0b11) => 44100,
+        (1, 0b11) => 48000,
+        (2, 0b11) => 32000,
+        (0, 0b10) => 22050,
+        (1, 0b10) => 24000,
+        (2, 0b10) => 16000,
+        (0, 0b00) => 11025,
+        (1, 0b00) => 12000,
+        (2, 0b00) => 8000,
+        _ => return None,
+    };
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+    let mono = (header >> 6) & 0b11 == 0b11;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = at + 4 + side_info;
+    if matches!(data.get(xing..xing + 4), Some(b"Xing") | Some(b"Info")) {
+        // The frame count is present if the first flag is set.
+        if be_u32(&data, xing + 4)? & 1 == 1 {
+            let frames = be_u32(&data, xing + 8)? as u64;
+            return duration_ms(frames * samples_per_frame, sample_rate);
+        }
+    }
+    let bitrate = bitrate_kbps[((header >> 12) & 0b1111) as usize] as u64 * 1000;
+    let audio_bytes = end.checked_sub(start + at as u64)?;
+    duration_ms(audio_bytes * 8, bitrate)
+}
+
+/// ID3v2.3 splits the recording time into year, DDMM and HHMM frames.
+fn parse_id3v23_time(frames: &HashMap<String, String>) -> Option<CaptureTime> {
+    let year = frames.get("TYER")?.trim();
+    // Frame text is untrusted, so only four ASCII digits are sliced by byte offset.
+    let digits = |text: &str| -> Option<(u32, u32)> {
+        let text = text.trim().as_bytes().get(0..4)?;
+        if !text.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let pair = |v: &[u8]| ((v[0] - b'0') * 10 + (v[1] - b'0')) as u32;
+        Some((pair(&text[0..2]), pair(&text[2..4])))
+    };
+    let (day, month) = digits(frames.get("TDAT")?)?;
+    let (hour, minute) = digits(frames.get("TIME")?)?;
+    let naive =
+        NaiveDate::from_ymd_opt(year.parse().ok()?, month, day)?.and_hms_opt(hour, minute, 0)?;
+    Some(local_capture_time(naive))
+}
+
+fn syncsafe(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0, |acc, v| (acc << 7) | (*v & 0x7F) as u32)
+}
+
+fn decode_id3_text(body: &[u8]) -> String {
+    let Some((encoding, text)) = body.split_first() else {
+        return String::new();
+    };
+    match encoding {
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                rest => (*encoding == 2, rest),
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|v| match big_endian {
+                    true => u16::from_be_bytes([v[0], v[1]]),
+                    false => u16::from_le_bytes([v[0], v[1]]),
+                })
+                .take_while(|v| *v != 0)
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).to_string(),
+        _ => text.iter().map(|v| *v as char).collect(),
+    }
+    .trim_end_matches('\0')
+    .to_string()
+}
+
+// ADTS (aac)
+
+fn read_adts<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    // Raw AAC may start with an ID3v2 tag as well.
+    let creation_time = read_id3(reader);
+    if creation_time.is_none() {
+        reader.seek(SeekFrom::Start(0)).ok()?;
+    }
+    let mut details = MediaDetails::default();
+    if let Some(duration) = read_adts_duration(reader) {
+        details.duration = Some(duration);
+        details.codec = Some("aac".to_string());
+    }
+    Some(FileMetadata {
+        creation_time: creation_time.flatten(),
+        details,
+        ..Default::default()
+    })
+}
+
+/// The duration of AAC audio from its ADTS frames, each of which holds 1024 samples per raw data
+/// block. Frames are counted up to the first one that is not an ADTS frame.
+fn read_adts_duration<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let mut sample_rate = None;
+    let mut samples = 0;
+    let mut header = [0; 7];
+    while reader.read_exact(&mut header).is_ok() {
+        // Frame sync and layer 0.
+        if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+            break;
+        }
+        let Some(rate) = ADTS_SAMPLE_RATES.get(((header[2] >> 2) & 0b1111) as usize) else {
+            break;
+        };
+        sample_rate.get_or_insert(*rate);
+        let length = ((header[3] as u64 & 0b11) << 11)
+            | ((header[4] as u64) << 3)
+            | ((header[5] as u64) >> 5);
+        if length < header.len() as u64 {
+            break;
+        }
+        samples += ((header[6] & 0b11) as u64 + 1) * 1024;
+        if skip(reader, length - header.len() as u64).is_none() {
+            break;
+        }
+    }
+    duration_ms(samples, sample_rate?).filter(|_| samples > 0)
+}
+
+// Ogg (opus, vorbis)
+
+fn read_ogg<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    // The comment header is the second packet of the stream, the first page only holds the
+    // identification header.
+    let mut identification = Vec::new();
+    let mut packets = Vec::new();
+    let mut page = 0;
+    while packets.len() < MAX_HEADER_SCAN as usize {
+        let mut header = [0; 27];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        if &header[0..4] != b"OggS" {
+            return None;
+        }
+        let mut segments = vec![0; header[26] as usize];
+        reader.read_exact(&mut segments).ok()?;
+        let size: u64 = segments.iter().map(|v| *v as u64).sum();
+        let mut payload = Vec::new();
+        reader.take(size).read_to_end(&mut payload).ok()?;
+        match page {
+            0 => identification = payload,
+            _ => packets.append(&mut payload),
+        }
+        page += 1;
+        // A segment shorter than 255 bytes terminates the packet.
+        if page > 1 && segments.last().is_some_and(|v| *v < 255) {
+            break;
+        }
+    }
+
+    let comments = packets
+        .strip_prefix(b"OpusTags")
+        .or_else(|| packets.strip_prefix(b"\x03vorbis"))?;
+    // Granule positions count samples at 48kHz after the pre-skip for opus and at the sample rate
+    // for vorbis.
+    let (codec, skip, rate) = match identification.as_slice() {
+        v if v.starts_with(b"OpusHead") => ("opus", le_u16(v, 10)? as u64, 48000),
+        v if v.starts_with(b"\x01vorbis") => ("vorbis", 0, le_u32(v, 12)? as u64),
+        _ => return None,
+    };
+    Some(FileMetadata {
+        creation_time: vorbis_comment_time(&parse_vorbis_comment(comments)),
+        details: MediaDetails {
+            codec: Some(codec.to_string()),
+            duration: last_ogg_granule(reader)
+                .and_then(|v| duration_ms(v.saturating_sub(skip), rate)),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
+
+/// The granule position of the last page, which is the total number of samples.
+fn last_ogg_granule<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let end = reader.seek(SeekFrom::End(0)).ok()?;
+    // A page is at most 65307 bytes long.
+    let start = end.saturating_sub(65536 + 27);
+    reader.seek(SeekFrom::Start(start)).ok()?;
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).ok()?;
+    let at = tail.windows(4).rposition(|v| v == b"OggS")?;
+    le_u64(&tail, at + 6)
+}
+
+fn parse_vorbis_comment(comments: &[u8]) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    let Some(vendor_length) = le_u32(comments, 0).map(|v| v as usize) else {
+        return tags;
+    };
+    let count = le_u32(comments, 4 + vendor_length).unwrap_or(0);
+    let mut at = 8 + vendor_length;
+    for _ in 0..count {
+        let Some(length) = le_u32(comments, at).map(|v| v as usize) else {
+            break;
+        };
+        let Some(comment) = comments.get(at + 4..at + 4 + length) else {
+            break;
+        };
+        if let Some((key, value)) = String::from_utf8_lossy(comment).split_once('=') {
+            tags.insert(key.to_uppercase(), value.to_string());
+        }
+        at += 4 + length;
+    }
+    tags
+}
+
+fn vorbis_comment_time(tags: &HashMap<String, String>) -> Option<CaptureTime> {
+    ["CREATION_TIME", "DATE"]
+        .iter()
+        .find_map(|v| tags.get(*v).and_then(|v| parse_text_time(v)))
+}
+
+// FLAC
+
+fn read_flac<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).ok()?;
+    if &magic != b"fLaC" {
+        return None;
+    }
+    let mut metadata = FileMetadata {
+        details: MediaDetails {
+            codec: Some("flac".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    loop {
+        let header = read_u32(reader)?;
+        let (last, kind, length) = (header >> 31 == 1, (header >> 24) & 0x7F, header & 0xFFFFFF);
+        match kind {
+            // Stream info: a 20 bit sample rate and a 36 bit sample count after the block sizes.
+            0 => {
+                let mut info = Vec::new();
+                reader.take(length as u64).read_to_end(&mut info).ok()?;
+                let packed = be_u64(&info, 10)?;
+                metadata.details.duration = duration_ms(packed & 0xF_FFFF_FFFF, packed >> 44);
+            }
+            // Vorbis comment.
+            4 => {
+                let mut comments = Vec::new();
+                reader.take(length as u64).read_to_end(&mut comments).ok()?;
+                metadata.creation_time = vorbis_comment_time(&parse_vorbis_comment(&comments));
+            }
+            _ => {
+                skip(reader, length as u64)?;
+            }
+        }
+        if last {
+            return Some(metadata);
+        }
+    }
+}
+
+// RIFF (wav)
+
+fn read_riff<R: Read + Seek>(reader: &mut R) -> Option<FileMetadata> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut creation_time = None;
+    let mut bext_time = None;
+    let mut details = MediaDetails::default();
+    let mut byte_rate = None;
+    let mut data_size = None;
+    loop {
+        let mut id = [0; 4];
+        if reader.read_exact(&mut id).is_err() {
+            break;
+        }
+        let size = u32::from_le_bytes(read_u32(reader)?.to_be_bytes()) as u64;
+        // Chunks are padded to an even size.
+        let padded = size + size % 2;
+        match &id {
+            // Broadcast wave extension, written by most field recorders.
+            b"bext" if size >= 338 => {
+                let mut bext = Vec::new();
+                reader.take(padded).read_to_end(&mut bext).ok()?;
+                // The chunk might be cut short by the end of the file.
+                if let (Some(date), Some(time)) = (bext.get(320..330), bext.get(330..338)) {
+                    let date = String::from_utf8_lossy(date).replace([':', '/', '.'], "-");
+                    let time = String::from_utf8_lossy(time).replace(['-', '.'], ":");
+                    bext_time = parse_text_time(&format!("{}T{}", date, time));
+                }
+            }
+            b"fmt " => {
+                let mut format = Vec::new();
+                reader.take(padded).read_to_end(&mut format).ok()?;
+                details.codec = match le_u16(&format, 0) {
+                    Some(1) | Some(0xFFFE) => Some("pcm".to_string()),
+                    Some(3) => Some("pcm_float".to_string()),
+                    Some(v) => Some(format!("0x{:04x}", v)),
+                    None => None,
+                };
+                byte_rate = le_u32(&format, 8);
+            }
+            b"data" => {
+                data_size = Some(size);
+                skip(reader, padded)?;
+            }
+            b"LIST" => {
+                let mut list = Vec::new();
+                reader.take(padded).read_to_end(&mut list).ok()?;
+                if list.starts_with(b"INFO") {
+                    let mut at = 4;
+                    while let Some(size) = list.get(at + 4..at + 8) {
+                        let size = u32::from_le_bytes(size.try_into().ok()?) as usize;
+                        let Some(value) = list.get(at + 8..at + 8 + size) else {
+                            break;
+                        };
+                        if &list[at..at + 4] == b"ICRD" {
+                            creation_time = parse_text_time(&String::from_utf8_lossy(value));
+                        }
+                        at += 8 + size + size % 2;
+                    }
+                }
+            }
+            _ => {
+                skip(reader, padded)?;
+            }
+        }
+    }
+    details.duration = duration_ms(data_size.unwrap_or(0), byte_rate.unwrap_or(0) as u64)
+        .filter(|_| data_size.is_some());
+    Some(FileMetadata {
+        creation_time: bext_time.or(creation_time),
+        details,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Cursor};
+
+    #[test]
+    fn corrupt_largesize_does_not_seek_backwards() {
+        // The size of the second box wraps to -24 as a seek offset, back to the start of the file.
+        let mut data = Vec::new();
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&(u64::MAX - 7).to_be_bytes());
+        data.extend_from_slice(&[0; 32]);
+        assert!(read_isobmff(&mut Cursor::new(data)).is_none());
+    }
+
+    #[test]
+    fn id3v23_time_rejects_non_ascii_frames() {
+        let frames = |date: &str, time: &str| {
+            HashMap::from([
+                ("TYER".to_string(), "2021".to_string()),
+                ("TDAT".to_string(), date.to_string()),
+                ("TIME".to_string(), time.to_string()),
+            ])
+        };
+        assert!(parse_id3v23_time(&frames("0306", "1430")).is_some());
+        assert!(parse_id3v23_time(&frames("0é06", "1430")).is_none());
+        assert!(parse_id3v23_time(&frames("03", "1430")).is_none());
+        assert!(parse_id3v23_time(&frames("0306", "14")).is_none());
+    }
+
+    #[test]
+    fn truncated_bext_chunk_is_skipped() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&1000u32.to_le_bytes());
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(b"bext");
+        data.extend_from_slice(&602u32.to_le_bytes());
+        data.extend_from_slice(&[0; 100]);
+        let metadata = read_riff(&mut Cursor::new(data)).unwrap();
+        assert!(metadata.creation_time.is_none());
+    }
+
+    #[test]
+    fn reads_adts_frames() {
+        let frame = |length: u16| {
+            // MPEG-4 AAC LC at 44.1 kHz in stereo with one raw data block.
+            let mut frame = vec![
+                0xFF,
+                0xF1,
+                0x50,
+                0x80 | (length >> 11) as u8,
+                (length >> 3) as u8,
+                ((length & 0b111) << 5) as u8 | 0x1F,
+                0xFC,
+            ];
+            frame.resize(length as usize, 0);
+            frame
+        };
+        let data: Vec<u8> = (0..43).flat_map(|_| frame(300)).collect();
+        let metadata = read_adts(&mut Cursor::new(data)).unwrap();
+        assert_eq!(metadata.details.codec.as_deref(), Some("aac"));
+        assert_eq!(metadata.details.duration, Some(43 * 1024 * 1000 / 44100));
+        assert!(read_adts(&mut Cursor::new(vec![0; 64]))
+            .unwrap()
+            .details
+            .codec
+            .is_none());
+    }
+}
@@ -0,0 +1,399 @@
+use {
+    rand::{distributions::Alphanumeric, Rng},
+    server_api::external::{
+        rocket::{
+            http::{ContentType, Header, Status},
+            request::Request,
+            response::{self, Responder, Response},
+        },
+        tokio::{
+            fs::File,
+            io::{AsyncRead, AsyncSeek, ReadBuf},
+        },
+        types::external::chrono::{DateTime, Utc},
+    },
+    std::{
+        collections::VecDeque,
+        fs::Metadata,
+        io::{self, Cursor, SeekFrom},
+        path::Path,
+        pin::Pin,
+        task::{ready, Context, Poll},
+    },
+};
+
+/// Requests with more ranges than this, after overlapping ones are merged, are answered with the
+/// whole file.
+const MAX_RANGES: usize = 32;
+
+/// A file served with support for `Range`, `If-Range` and conditional requests.
+pub struct FileResponse {
+    file: File,
+    length: u64,
+    content_type: ContentType,
+    modified: Option<DateTime<Utc>>,
+    etag: String,
+}
+
+impl FileResponse {
+    pub async fn open(path: &Path, content_type: ContentType) -> io::Result<Self> {
+        let file = File::open(path).await?;
+        let metadata = file.metadata().await?;
+        let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+        Ok(FileResponse {
+            file,
+            length: metadata.len(),
+            content_type,
+            etag: entity_tag(&metadata, modified),
+            modified,
+        })
+    }
+
+    fn last_modified(&self) -> Option<String> {
+        self.modified
+            .map(|v| v.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    }
+
+    /// Whether the `If-Range` precondition holds, so the requested ranges can be served.
+    fn if_range_matches(&self, request: &Request<'_>) -> bool {
+        match request.headers().get_one("If-Range").map(str::trim) {
+            None => true,
+            Some(v) if v.starts_with('"') => v == self.etag,
+            Some(v) => self.last_modified().is_some_and(|modified| modified == v),
+        }
+    }
+
+    /// Whether the client already has the current version of the file. `If-Modified-Since` is
+    /// only considered without `If-None-Match`.
+    fn is_not_modified(&self, request: &Request<'_>) -> bool {
+        if let Some(v) = request.headers().get_one("If-None-Match") {
+            return v
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag);
+        }
+        match (
+            request.headers().get_one("If-Modified-Since"),
+            self.modified,
+        ) {
+            (Some(since), Some(modified)) => DateTime::parse_from_rfc2822(since)
+                .is_ok_and(|since| modified.timestamp() <= since.timestamp()),
+            _ => false,
+        }
+    }
+}
+
+/// A strong entity tag from the size, modification time and inode of a file, which change
+/// whenever its content is replaced.
+fn entity_tag(metadata: &Metadata, modified: Option<DateTime<Utc>>) -> String {
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    format!(
+        "\"{:x}-{:x}-{:x}\"",
+        metadata.len(),
+        modified
+            .and_then(|v| v.timestamp_nanos_opt())
+            .unwrap_or_default(),
+        inode
+    )
+}
+
+impl<'r> Responder<'r, 'static> for FileResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag.clone());
+        if let Some(v) = self.last_modified() {
+            response.raw_header("Last-Modified", v);
+        }
+        if self.is_not_modified(request) {
+            return response.status(Status::NotModified).ok();
+        }
+
+        let ranges = match request.headers().get_one("Range") {
+            Some(v) if self.if_range_matches(request) => parse_range(v, self.length),
+            _ => None,
+        };
+        let body = match ranges {
+            None => {
+                response.header(self.content_type);
+                RangeBody::new(self.file, vec![Part::file(0, self.length)])
+            }
+            Some(ranges) if ranges.is_empty() => {
+                return response
+                    .status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", self.length))
+                    .ok();
+            }
+            Some(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                response
+                    .status(Status::PartialContent)
+                    .header(self.content_type)
+                    .raw_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, self.length),
+                    );
+                RangeBody::new(self.file, vec![Part::file(start, end - start + 1)])
+            }
+            Some(ranges) => {
+                let boundary: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect();
+                let mut parts = Vec::new();
+                for (start, end) in ranges {
+                    parts.push(Part::bytes(format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, self.content_type, start, end, self.length
+                    )));
+                    parts.push(Part::file(start, end - start + 1));
+                }
+                parts.push(Part::bytes(format!("\r\n--{}--\r\n", boundary)));
+                response.status(Status::PartialContent).header(Header::new(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                ));
+                RangeBody::new(self.file, parts)
+            }
+        };
+        let size = usize::try_from(body.size()).map_err(|_| Status::InternalServerError)?;
+        response.sized_body(size, body).ok()
+    }
+}
+
+/// Parses a `Range` header into sorted inclusive byte ranges, with overlapping and adjacent ones
+/// merged so no byte is sent twice. Returns `None` if the header should be ignored and an empty
+/// list if none of the ranges can be satisfied.
+fn parse_range(header: &str, length: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = header.trim().strip_prefix("bytes=")?.split(',');
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.trim().split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return None,
+            ("", suffix) => {
+                let suffix = suffix.parse::<u64>().ok()?;
+                match suffix.min(length) {
+                    0 => None,
+                    v => Some((length - v, length - 1)),
+                }
+            }
+            (start, end) => {
+                let start = start.parse::<u64>().ok()?;
+                let end = match end {
+                    "" => u64::MAX,
+                    v => v.parse::<u64>().ok()?,
+                };
+                if end < start {
+                    return None;
+                }
+                match start < length {
+                    true => Some((start, end.min(length - 1))),
+                    false => None,
+                }
+            }
+        };
+        ranges.extend(range);
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    match merged.len() > MAX_RANGES {
+        true => None,
+        false => Some(merged),
+    }
+}
+
+enum Part {
+    Bytes(Cursor<Vec<u8>>),
+    File {
+        start: u64,
+        remaining: u64,
+        positioned: bool,
+    },
+}
+
+impl Part {
+    fn bytes(text: String) -> Self {
+        Part::Bytes(Cursor::new(text.into_bytes()))
+    }
+
+    fn file(start: u64, length: u64) -> Self {
+        Part::File {
+            start,
+            remaining: length,
+            positioned: false,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self {
+            Part::Bytes(v) => v.get_ref().len() as u64,
+            Part::File { remaining, .. } => *remaining,
+        }
+    }
+}
+
+/// A response body made of literal bytes and sections of a file.
+struct RangeBody {
+    file: File,
+    parts: VecDeque<Part>,
+    seeking: bool,
+}
+
+impl RangeBody {
+    fn new(file: File, parts: Vec<Part>) -> Self {
+        RangeBody {
+            file,
+            parts: parts.into(),
+            seeking: false,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.parts.iter().map(Part::size).sum()
+    }
+}
+
+impl AsyncRead for RangeBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let Some(part) = this.parts.front_mut() else {
+                return Poll::Ready(Ok(()));
+            };
+            match part {
+                Part::Bytes(cursor) => {
+                    let remaining = &cursor.get_ref()[cursor.position() as usize..];
+                    if remaining.is_empty() {
+                        this.parts.pop_front();
+                        continue;
+                    }
+                    let length = remaining.len().min(buf.remaining());
+                    buf.put_slice(&remaining[..length]);
+                    cursor.set_position(cursor.position() + length as u64);
+                    return Poll::Ready(Ok(()));
+                }
+                Part::File {
+                    start,
+                    remaining,
+                    positioned,
+                } => {
+                    if *remaining == 0 {
+                        this.parts.pop_front();
+                        continue;
+                    }
+                    if !*positioned {
+                        if !this.seeking {
+                            Pin::new(&mut this.file).start_seek(SeekFrom::Start(*start))?;
+                            this.seeking = true;
+                        }
+                        ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+                        this.seeking = false;
+                        *positioned = true;
+                    }
+                    let length = (*remaining).min(buf.remaining() as u64) as usize;
+                    let mut limited = ReadBuf::new(buf.initialize_unfilled_to(length));
+                    ready!(Pin::new(&mut this.file).poll_read(cx, &mut limited))?;
+                    let read = limited.filled().len();
+                    if read == 0 {
+                        // The file got shorter since the response was started.
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    buf.advance(read);
+                    *remaining -= read as u64;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for RangeBody {
+    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> io::Result<()> {
+        // The size of the body is always preset, so Rocket never has to seek it.
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Err(io::ErrorKind::Unsupported.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(parse_range(" bytes=10 - 20 ", 1000), Some(vec![(10, 20)]));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(vec![(900, 999)]));
+    }
+
+    #[test]
+    fn parses_open_ended_and_suffix_ranges() {
+        assert_eq!(parse_range("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_are_empty() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(vec![]));
+        assert_eq!(parse_range("bytes=0-", 0), Some(vec![]));
+        assert_eq!(parse_range("bytes=2000-3000,0-9", 1000), Some(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn invalid_headers_are_ignored() {
+        assert_eq!(parse_range("bytes=20-10", 1000), None);
+        assert_eq!(parse_range("bytes=-", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("items=0-10", 1000), None);
+        assert_eq!(parse_range("bytes=0-10,20-10", 1000), None);
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored() {
+        let ranges = |count: u64| {
+            (0..count)
+                .map(|v| format!("{}-{}", v * 10, v * 10 + 4))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let allowed = parse_range(&format!("bytes={}", ranges(MAX_RANGES as u64)), 1000);
+        assert_eq!(allowed.map(|v| v.len()), Some(MAX_RANGES));
+        let header = format!("bytes={}", ranges(MAX_RANGES as u64 + 1));
+        assert_eq!(parse_range(&header, 1000), None);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_merged() {
+        let header = format!("bytes={}", vec!["0-"; MAX_RANGES * 4].join(","));
+        assert_eq!(parse_range(&header, 1000), Some(vec![(0, 999)]));
+        assert_eq!(
+            parse_range("bytes=50-99,0-9,10-19,5-7,-10", 1000),
+            Some(vec![(0, 19), (50, 99), (990, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-9,11-19", 1000),
+            Some(vec![(0, 9), (11, 19)])
+        );
+    }
+}
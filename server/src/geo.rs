@@ -0,0 +1,280 @@
+use {
+    serde::{Deserialize, Serialize},
+    server_api::{
+        db::Database,
+        external::types::{
+            available_plugins::AvailablePlugins,
+            external::mongodb::{
+                bson::{doc, Document},
+                error::Error,
+                options::IndexOptions,
+                IndexModel,
+            },
+        },
+    },
+};
+
+/// The field of an event that holds its position.
+const POSITION_FIELD: &str = "event.position";
+
+/// Mean radius of the earth in meters, as used by MongoDB for spherical queries.
+const EARTH_RADIUS: f64 = 6_378_100.0;
+
+/// Where a file was captured, in degrees and meters above sea level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+impl GpsPosition {
+    /// Returns `None` for coordinates that are not on earth.
+    pub fn new(latitude: f64, longitude: f64, altitude: Option<f64>) -> Option<Self> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+        Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude: altitude.filter(|v| v.is_finite()),
+        })
+    }
+}
+
+/// A GeoJSON geometry, the form MongoDB indexes with a `2dsphere` index.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    /// Longitude and latitude, in this order.
+    Point { coordinates: [f64; 2] },
+}
+
+impl From<GpsPosition> for Geometry {
+    fn from(value: GpsPosition) -> Self {
+        Geometry::Point {
+            coordinates: [value.longitude, value.latitude],
+        }
+    }
+}
+
+/// Parses an ISO 6709 location as written by phones into QuickTime metadata, e.g.
+/// `+37.3349-122.0090+027.000/`. Degrees may also be given as `DDMM.M` or `DDMMSS.S`.
+pub fn parse_iso6709(text: &str) -> Option<GpsPosition> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let text = text.split("CRS").next()?.trim_end_matches('/');
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            parts.push(&text[start..i]);
+            start = i;
+        }
+    }
+    parts.push(&text[start..]);
+    GpsPosition::new(
+        iso6709_angle(parts.first()?, 2)?,
+        iso6709_angle(parts.get(1)?, 3)?,
+        parts.get(2).and_then(|v| v.parse().ok()),
+    )
+}
+
+fn iso6709_angle(part: &str, degree_digits: usize) -> Option<f64> {
+    let sign = match part.get(..1)? {
+        "+" => 1.0,
+        "-" => -1.0,
+        _ => return None,
+    };
+    let digits = &part[1..];
+    let value: f64 = digits.parse().ok()?;
+    let angle = match digits
+        .find('.')
+        .unwrap_or(digits.len())
+        .checked_sub(degree_digits)?
+    {
+        0 => value,
+        2 => (value / 100.0).trunc() + (value % 100.0) / 60.0,
+        4 => {
+            (value / 10000.0).trunc()
+                + (value / 100.0).trunc() % 100.0 / 60.0
+                + (value % 100.0) / 3600.0
+        }
+        _ => return None,
+    };
+    Some(sign * angle)
+}
+
+/// An area media is searched in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GeoArea {
+    /// Bounds in degrees. A box with `west` greater than `east` crosses the antimeridian.
+    BoundingBox {
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+    },
+    /// A radius in meters around a position.
+    Circle {
+        latitude: f64,
+        longitude: f64,
+        radius: f64,
+    },
+}
+
+impl GeoArea {
+    /// Parses a `west,south,east,north` bounding box or a `latitude,longitude` center with a
+    /// radius. Exactly one of both has to be given.
+    pub fn parse(bbox: Option<&str>, near: Option<&str>, radius: Option<f64>) -> Option<Self> {
+        match (bbox, near, radius) {
+            (Some(bbox), None, None) => {
+                let bounds = parse_numbers(bbox)?;
+                let [west, south, east, north] = bounds[..] else {
+                    return None;
+                };
+                GpsPosition::new(south, west, None)?;
+                GpsPosition::new(north, east, None)?;
+                (south <= north).then_some(GeoArea::BoundingBox {
+                    west,
+                    south,
+                    east,
+                    north,
+                })
+            }
+            (None, Some(near), Some(radius)) if radius.is_finite() && radius >= 0.0 => {
+                let center = parse_numbers(near)?;
+                let [latitude, longitude] = center[..] else {
+                    return None;
+                };
+                let center = GpsPosition::new(latitude, longitude, None)?;
+                Some(GeoArea::Circle {
+                    latitude: center.latitude,
+                    longitude: center.longitude,
+                    radius,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// A filter for events with a position inside of the area.
+    pub fn filter(&self) -> Document {
+        match *self {
+            // Edges of GeoJSON polygons are great circles, which do not follow latitudes, so boxes
+            // compare the coordinates directly.
+            GeoArea::BoundingBox {
+                west,
+                south,
+                east,
+                north,
+            } => {
+                let longitude = format!("{}.coordinates.0", POSITION_FIELD);
+                let latitude = format!("{}.coordinates.1", POSITION_FIELD);
+                let longitude_filter = match west <= east {
+                    true => doc! { &longitude: { "$gte": west, "$lte": east } },
+                    false => doc! { "$or": [
+                        { &longitude: { "$gte": west } },
+                        { &longitude: { "$lte": east } },
+                    ] },
+                };
+                doc! { "$and": [
+                    { &latitude: { "$gte": south, "$lte": north } },
+                    longitude_filter,
+                ] }
+            }
+            GeoArea::Circle {
+                latitude,
+                longitude,
+                radius,
+            } => doc! {
+                POSITION_FIELD: {
+                    "$geoWithin": {
+                        "$centerSphere": [[longitude, latitude], radius / EARTH_RADIUS]
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn parse_numbers(text: &str) -> Option<Vec<f64>> {
+    text.split(',')
+        .map(|v| v.trim().parse::<f64>().ok().filter(|v| v.is_finite()))
+        .collect()
+}
+
+/// Creates the geospatial index on the positions of media events. It is limited to events of this
+/// plugin, as other plugins might use the same field for something else.
+pub async fn create_index(database: &Database) -> Result<(), Error> {
+    database
+        .get_events::<Document>()
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { POSITION_FIELD: "2dsphere" })
+                .options(
+                    IndexOptions::builder()
+                        .name("timeline_plugin_media_scan_position".to_string())
+                        .partial_filter_expression(Database::generate_find_plugin_filter(
+                            AvailablePlugins::timeline_plugin_media_scan,
+                        ))
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(position: Option<GpsPosition>, latitude: f64, longitude: f64) {
+        let position = position.expect("position parses");
+        assert!(
+            (position.latitude - latitude).abs() < 1e-6,
+            "{:?}",
+            position
+        );
+        assert!(
+            (position.longitude - longitude).abs() < 1e-6,
+            "{:?}",
+            position
+        );
+    }
+
+    #[test]
+    fn parses_decimal_degrees() {
+        let position = parse_iso6709("+37.3349-122.0090+027.000/");
+        assert_near(position, 37.3349, -122.009);
+        assert_eq!(position.and_then(|v| v.altitude), Some(27.0));
+        assert_near(parse_iso6709("-33.8688+151.2093/"), -33.8688, 151.2093);
+        assert_eq!(parse_iso6709("+48.8584+002.2945/").unwrap().altitude, None);
+    }
+
+    #[test]
+    fn parses_minutes_and_seconds() {
+        assert_near(parse_iso6709("+4851.50+00217.70/"), 48.858333, 2.295);
+        assert_near(parse_iso6709("-335212.0+1511234.0/"), -33.87, 151.209444);
+    }
+
+    #[test]
+    fn ignores_padding_and_reference_system() {
+        assert_near(
+            parse_iso6709(" +37.3349-122.0090+027.000CRSWGS_84/\0"),
+            37.3349,
+            -122.009,
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_positions() {
+        assert_eq!(parse_iso6709(""), None);
+        assert_eq!(parse_iso6709("+37.3349"), None);
+        assert_eq!(parse_iso6709("37.3349-122.0090/"), None);
+        assert_eq!(parse_iso6709("+97.0000+010.0000/"), None);
+        assert_eq!(parse_iso6709("+3.3349-122.0090/"), None);
+        assert_eq!(parse_iso6709("+é7.3349-122.0090/"), None);
+    }
+}
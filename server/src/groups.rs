@@ -0,0 +1,244 @@
+use {
+    crate::{media_type::MediaKind, Media, TimeSource},
+    serde::{Deserialize, Serialize},
+    std::collections::HashMap,
+};
+
+/// Files without a burst id count as a burst if at least this many images were taken with at most
+/// [`BURST_GAP`] milliseconds between each. Only capture times with a fraction of a second are
+/// precise enough for this, ordinary shots a second apart would look like a burst otherwise.
+const MIN_BURST_SIZE: usize = 3;
+const BURST_GAP: i64 = 500;
+
+/// Videos paired with an image by name only are Live Photos if they are at most this long.
+const MAX_LIVE_PHOTO_DURATION: u64 = 5000;
+
+/// What cameras record to tie files that were taken together to each other.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CaptureIdentifiers {
+    /// Shared by the still and the video of a Live Photo.
+    pub content_identifier: Option<String>,
+    /// Shared by the shots of a burst.
+    pub burst_id: Option<String>,
+}
+
+/// Files that were taken as one.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MediaGroup {
+    pub id: String,
+    pub kind: GroupKind,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupKind {
+    /// A still image and the short video around it.
+    LivePhoto,
+    /// Images shot in quick succession.
+    Burst,
+}
+
+/// Finds the Live Photos and bursts among the files of one directory. Returns the group of every
+/// grouped file by path.
+pub fn detect_groups(media: &[&Media]) -> HashMap<String, MediaGroup> {
+    let mut groups = HashMap::new();
+
+    // Live Photos share a content identifier, or at least the name of the files.
+    let mut by_identifier: HashMap<&str, Vec<&Media>> = HashMap::new();
+    for v in media {
+        if let Some(identifier) = &v.identifiers.content_identifier {
+            by_identifier.entry(identifier).or_default().push(v);
+        }
+    }
+    for (identifier, members) in by_identifier {
+        if is_live_photo(&members) {
+            let id = format!("live:{}", identifier);
+            insert_group(&mut groups, &members, id, GroupKind::LivePhoto);
+        }
+    }
+    let mut by_stem: HashMap<String, Vec<&Media>> = HashMap::new();
+    for v in media.iter().filter(|v| !groups.contains_key(&v.path)) {
+        by_stem
+            .entry(stem(&v.path).to_lowercase())
+            .or_default()
+            .push(v);
+    }
+    for members in by_stem.into_values() {
+        let short = members.iter().all(|v| {
+            v.kind != Some(MediaKind::Video)
+                || v.details.duration.unwrap_or(0) <= MAX_LIVE_PHOTO_DURATION
+        });
+        if members.len() == 2 && short && is_live_photo(&members) {
+            let id = format!("live:{}", stem(&members[0].path));
+            insert_group(&mut groups, &members, id, GroupKind::LivePhoto);
+        }
+    }
+
+    let mut images: Vec<&Media> = media
+        .iter()
+        .filter(|v| v.kind == Some(MediaKind::Image) && !groups.contains_key(&v.path))
+        .copied()
+        .collect();
+    let mut by_burst: HashMap<&str, Vec<&Media>> = HashMap::new();
+    for v in images.iter() {
+        if let Some(burst) = &v.identifiers.burst_id {
+            by_burst.entry(burst).or_default().push(v);
+        }
+    }
+    for (burst, members) in by_burst {
+        if members.len() > 1 {
+            let id = format!("burst:{}", burst);
+            insert_group(&mut groups, &members, id, GroupKind::Burst);
+        }
+    }
+
+    // A time that is exactly on a second is taken as one without a fraction, which at worst keeps
+    // a shot out of its burst.
+    images.retain(|v| {
+        v.identifiers.burst_id.is_none()
+            && v.time_source != TimeSource::FileModified
+            && v.time_modified.timestamp_subsec_nanos() != 0
+    });
+    images.sort_by_key(|v| v.time_modified);
+    for run in
+        images.chunk_by(|a, b| (b.time_modified - a.time_modified).num_milliseconds() <= BURST_GAP)
+    {
+        if run.len() >= MIN_BURST_SIZE {
+            let id = format!("burst:{}", run[0].path);
+            insert_group(&mut groups, run, id, GroupKind::Burst);
+        }
+    }
+    groups
+}
+
+fn insert_group(
+    groups: &mut HashMap<String, MediaGroup>,
+    members: &[&Media],
+    id: String,
+    kind: GroupKind,
+) {
+    for member in members {
+        groups.insert(
+            member.path.clone(),
+            MediaGroup {
+                id: id.clone(),
+                kind,
+            },
+        );
+    }
+}
+
+fn is_live_photo(members: &[&Media]) -> bool {
+    let count = |kind| members.iter().filter(|v| v.kind == Some(kind)).count();
+    count(MediaKind::Image) == 1 && count(MediaKind::Video) == 1
+}
+
+/// The path of a file without its extension.
+fn stem(path: &str) -> &str {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => stem,
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::MediaDetails,
+        server_api::external::types::external::chrono::{DateTime, Duration, Utc},
+    };
+
+    fn media(path: &str, kind: MediaKind, milliseconds: i64) -> Media {
+        Media {
+            path: path.to_string(),
+            time_modified: DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap()
+                + Duration::milliseconds(milliseconds),
+            location_name: "test".to_string(),
+            time_source: TimeSource::Exif,
+            size: 0,
+            sample_hash: None,
+            content_hash: None,
+            perceptual_hash: None,
+            kind: Some(kind),
+            mime_type: None,
+            details: MediaDetails::default(),
+            position: None,
+            altitude: None,
+            identifiers: CaptureIdentifiers::default(),
+            description: None,
+            group: None,
+            version: 0,
+        }
+    }
+
+    fn kinds(media: &[Media]) -> Vec<Option<GroupKind>> {
+        let groups = detect_groups(&media.iter().collect::<Vec<_>>());
+        media
+            .iter()
+            .map(|v| groups.get(&v.path).map(|v| v.kind))
+            .collect()
+    }
+
+    #[test]
+    fn pairs_live_photos_by_identifier() {
+        let mut still = media("/a/IMG_1.HEIC", MediaKind::Image, 0);
+        let mut video = media("/a/clip.mov", MediaKind::Video, 0);
+        still.identifiers.content_identifier = Some("X".to_string());
+        video.identifiers.content_identifier = Some("X".to_string());
+        let other = media("/a/IMG_2.HEIC", MediaKind::Image, 0);
+        let live = Some(GroupKind::LivePhoto);
+        assert_eq!(kinds(&[still, video, other]), vec![live, live, None]);
+    }
+
+    #[test]
+    fn pairs_live_photos_by_name_only_if_short() {
+        let mut video = media("/a/img_1.mov", MediaKind::Video, 0);
+        video.details.duration = Some(3000);
+        let still = media("/a/IMG_1.JPG", MediaKind::Image, 0);
+        let live = Some(GroupKind::LivePhoto);
+        assert_eq!(kinds(&[still.clone(), video.clone()]), vec![live, live]);
+        video.details.duration = Some(60_000);
+        assert_eq!(kinds(&[still, video]), vec![None, None]);
+    }
+
+    #[test]
+    fn groups_bursts_by_id() {
+        let shots: Vec<Media> = (0..2)
+            .map(|i| {
+                let mut v = media(&format!("/a/{}.jpg", i), MediaKind::Image, i * 5000);
+                v.identifiers.burst_id = Some("B".to_string());
+                v
+            })
+            .collect();
+        assert_eq!(kinds(&shots), vec![Some(GroupKind::Burst); 2]);
+    }
+
+    #[test]
+    fn groups_bursts_by_precise_time() {
+        let shots: Vec<Media> = (0..4)
+            .map(|i| media(&format!("/a/{}.jpg", i), MediaKind::Image, 120 + i * 100))
+            .collect();
+        assert_eq!(kinds(&shots), vec![Some(GroupKind::Burst); 4]);
+    }
+
+    #[test]
+    fn whole_second_times_are_no_burst() {
+        let shots: Vec<Media> = (0..4)
+            .map(|i| media(&format!("/a/{}.jpg", i), MediaKind::Image, i * 1000))
+            .collect();
+        assert_eq!(kinds(&shots), vec![None; 4]);
+        let slow: Vec<Media> = (0..4)
+            .map(|i| media(&format!("/a/{}.jpg", i), MediaKind::Image, 120 + i * 800))
+            .collect();
+        assert_eq!(kinds(&slow), vec![None; 4]);
+        let mut modified = media("/a/0.jpg", MediaKind::Image, 120);
+        modified.time_source = TimeSource::FileModified;
+        let shots = [
+            modified,
+            media("/a/1.jpg", MediaKind::Image, 220),
+            media("/a/2.jpg", MediaKind::Image, 320),
+        ];
+        assert_eq!(kinds(&shots), vec![None; 3]);
+    }
+}
@@ -0,0 +1,54 @@
+use {
+    server_api::external::tokio::{
+        fs::File,
+        io::{AsyncReadExt, AsyncSeekExt},
+        task,
+    },
+    std::{
+        io::{self, SeekFrom},
+        path::Path,
+    },
+    xxhash_rust::xxh3::Xxh3,
+};
+
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Hashes the size together with the first and last 64 KiB of a file. Cheap enough to run for
+/// every new file while still telling apart files of the same size.
+pub async fn sample_hash(path: &Path) -> Result<String, io::Error> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let mut hasher = Xxh3::new();
+    hasher.update(&size.to_le_bytes());
+
+    let mut buffer = Vec::with_capacity(SAMPLE_SIZE as usize);
+    (&mut file)
+        .take(SAMPLE_SIZE)
+        .read_to_end(&mut buffer)
+        .await?;
+    hasher.update(&buffer);
+    if size > SAMPLE_SIZE {
+        buffer.clear();
+        file.seek(SeekFrom::Start(
+            size.saturating_sub(SAMPLE_SIZE).max(SAMPLE_SIZE),
+        ))
+        .await?;
+        file.read_to_end(&mut buffer).await?;
+        hasher.update(&buffer);
+    }
+
+    Ok(format!("{:032x}", hasher.digest128()))
+}
+
+/// BLAKE3 of the whole file. It is only computed for files whose size and sample hash match
+/// another file, as it reads all of it.
+pub async fn content_hash(path: &Path) -> Result<String, io::Error> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(std::fs::File::open(path)?)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
@@ -0,0 +1,177 @@
+use {
+    crate::{geo::GpsPosition, groups::CaptureIdentifiers, CaptureTime, MediaDetails, TimeSource},
+    exif::{DateTime as ExifDateTime, Exif, In, Reader, Tag, Value},
+    image::ImageReader,
+    server_api::external::types::external::chrono::{
+        DateTime, FixedOffset, Local, NaiveDate, TimeZone, Utc,
+    },
+    std::{fs::File, io::BufReader, path::Path},
+};
+
+const APPLE_MAKER_NOTE: &[u8] = b"Apple iOS\0";
+const APPLE_MAKER_NOTE_IFD: usize = 14;
+const APPLE_BURST_UUID: u16 = 0x000b;
+const APPLE_CONTENT_IDENTIFIER: u16 = 0x0011;
+
+pub fn read_exif(path: &Path) -> Option<Exif> {
+    let file = File::open(path).ok()?;
+    Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+pub fn read_capture_time(exif: &Exif) -> Option<CaptureTime> {
+    let mut date_time =
+        ascii_field(exif, Tag::DateTimeOriginal).and_then(|v| ExifDateTime::from_ascii(v).ok())?;
+    if let Some(subsec) = ascii_field(exif, Tag::SubSecTimeOriginal) {
+        let _ = date_time.parse_subsec(subsec);
+    }
+    if let Some(offset) = ascii_field(exif, Tag::OffsetTimeOriginal) {
+        let _ = date_time.parse_offset(offset);
+    }
+
+    let naive = NaiveDate::from_ymd_opt(
+        date_time.year as i32,
+        date_time.month as u32,
+        date_time.day as u32,
+    )?
+    .and_hms_nano_opt(
+        date_time.hour as u32,
+        date_time.minute as u32,
+        date_time.second as u32,
+        date_time.nanosecond.unwrap_or(0),
+    )?;
+
+    match date_time.offset {
+        Some(offset) => Some(CaptureTime {
+            time: FixedOffset::east_opt(offset as i32 * 60)?
+                .from_local_datetime(&naive)
+                .single()?
+                .with_timezone(&Utc),
+            source: TimeSource::Exif,
+        }),
+        // Without an offset the camera recorded wall clock time, the best guess is the
+        // timezone the server is running in.
+        None => Some(CaptureTime {
+            time: Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|v| v.with_timezone(&Utc))
+                .unwrap_or_else(|| DateTime::from_naive_utc_and_offset(naive, Utc)),
+            source: TimeSource::ExifLocalTime,
+        }),
+    }
+}
+
+/// The size and orientation of an image. The size is read from the image itself if it can be
+/// decoded and from the EXIF data otherwise.
+pub fn read_details(path: &Path, exif: Option<&Exif>) -> MediaDetails {
+    let size = ImageReader::open(path)
+        .and_then(|v| v.with_guessed_format())
+        .ok()
+        .and_then(|v| v.into_dimensions().ok())
+        .or_else(|| {
+            let exif = exif?;
+            Some((
+                uint_field(exif, Tag::PixelXDimension)?,
+                uint_field(exif, Tag::PixelYDimension)?,
+            ))
+        });
+    MediaDetails {
+        width: size.map(|v| v.0),
+        height: size.map(|v| v.1),
+        orientation: exif
+            .and_then(|v| uint_field(v, Tag::Orientation))
+            .and_then(|v| u16::try_from(v).ok())
+            .filter(|v| (1..=8).contains(v)),
+        ..Default::default()
+    }
+}
+
+pub fn read_position(exif: &Exif) -> Option<GpsPosition> {
+    let latitude = match ascii_field(exif, Tag::GPSLatitudeRef)? {
+        b"S" => -degrees_field(exif, Tag::GPSLatitude)?,
+        _ => degrees_field(exif, Tag::GPSLatitude)?,
+    };
+    let longitude = match ascii_field(exif, Tag::GPSLongitudeRef)? {
+        b"W" => -degrees_field(exif, Tag::GPSLongitude)?,
+        _ => degrees_field(exif, Tag::GPSLongitude)?,
+    };
+    // Reference 1 means below sea level.
+    let altitude = exif
+        .get_field(Tag::GPSAltitude, In::PRIMARY)
+        .and_then(|v| match &v.value {
+            Value::Rational(v) => v.first().map(|v| v.to_f64()),
+            _ => None,
+        })
+        .map(|v| match uint_field(exif, Tag::GPSAltitudeRef) {
+            Some(1) => -v,
+            _ => v,
+        });
+    GpsPosition::new(latitude, longitude, altitude)
+}
+
+/// The identifiers Apple devices keep in their maker note, which is an IFD with big endian values
+/// and offsets relative to the start of the note.
+pub fn read_identifiers(exif: &Exif) -> CaptureIdentifiers {
+    let note = match exif
+        .get_field(Tag::MakerNote, In::PRIMARY)
+        .map(|v| &v.value)
+    {
+        Some(Value::Undefined(v, _))
+            if v.starts_with(APPLE_MAKER_NOTE) && v.get(12..14) == Some(&b"MM"[..]) =>
+        {
+            v
+        }
+        _ => return CaptureIdentifiers::default(),
+    };
+    let be_u16 = |at: usize| Some(u16::from_be_bytes(note.get(at..at + 2)?.try_into().ok()?));
+    let be_u32 = |at: usize| Some(u32::from_be_bytes(note.get(at..at + 4)?.try_into().ok()?));
+    let ascii_entry = |tag: u16| {
+        let count = be_u16(APPLE_MAKER_NOTE_IFD)? as usize;
+        (0..count).find_map(|i| {
+            let entry = APPLE_MAKER_NOTE_IFD + 2 + i * 12;
+            if be_u16(entry)? != tag || be_u16(entry + 2)? != 2 {
+                return None;
+            }
+            let length = be_u32(entry + 4)? as usize;
+            let start = match length {
+                0..=4 => entry + 8,
+                _ => be_u32(entry + 8)? as usize,
+            };
+            let value = note.get(start..start.checked_add(length)?)?;
+            let value = String::from_utf8_lossy(value)
+                .trim_end_matches('\0')
+                .to_string();
+            (!value.is_empty()).then_some(value)
+        })
+    };
+    CaptureIdentifiers {
+        content_identifier: ascii_entry(APPLE_CONTENT_IDENTIFIER),
+        burst_id: ascii_entry(APPLE_BURST_UUID),
+    }
+}
+
+/// An angle stored as degrees, minutes and seconds.
+fn degrees_field(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) if !v.is_empty() => Some(
+            v.iter()
+                .zip([1.0, 60.0, 3600.0])
+                .map(|(v, unit)| v.to_f64() / unit)
+                .sum(),
+        ),
+        _ => None,
+    }
+}
+
+fn uint_field(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<&[u8]> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(ref v) => v.first().map(|v| v.as_slice()),
+        _ => None,
+    }
+}
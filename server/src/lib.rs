@@ -1,11 +1,28 @@
+mod container_metadata;
+mod file_response;
+mod geo;
+mod groups;
+mod hashing;
+mod image_metadata;
+mod media_type;
+mod paths;
+mod scan_filter;
+mod sidecar;
+mod signing;
+mod similarity;
+mod thumbnail;
+mod walk;
+mod watcher;
+
 use {
-    base64::Engine,
-    rsa::{
-        pkcs1v15::{Signature, SigningKey, VerifyingKey},
-        sha2::Sha256,
-        signature::{Keypair, RandomizedSigner, SignatureEncoding, Verifier},
-        RsaPrivateKey,
-    },
+    file_response::FileResponse,
+    geo::{GeoArea, Geometry, GpsPosition},
+    groups::{CaptureIdentifiers, GroupKind, MediaGroup},
+    ignore::gitignore::Gitignore,
+    media_type::{MediaKind, MediaType, MetadataFormat},
+    notify::RecommendedWatcher,
+    rsa::RsaPrivateKey,
+    scan_filter::ScanFilter,
     serde::{Deserialize, Serialize},
     server_api::{
         cache::Cache,
@@ -15,12 +32,16 @@ use {
             futures::{self, StreamExt, TryStreamExt},
             rocket::{
                 self, get,
-                http::{CookieJar, Status},
-                routes, Build, Rocket, State,
+                http::{ContentType, CookieJar, Status},
+                routes,
+                serde::json::Json,
+                Build, Rocket, State,
             },
             tokio::{
                 fs::{self, File},
-                sync::RwLock,
+                io::AsyncReadExt,
+                sync::{mpsc, RwLock},
+                task,
             },
             toml,
             types::{
@@ -29,25 +50,32 @@ use {
                 available_plugins::AvailablePlugins,
                 external::{
                     chrono::{self, DateTime, Utc},
-                    mongodb::bson::doc,
+                    mongodb::{
+                        bson::{doc, to_bson, Bson, Document},
+                        error::Error,
+                        options::IndexOptions,
+                        IndexModel,
+                    },
                     serde_json,
                 },
-                timing::Timing,
+                timing::{TimeRange, Timing},
             },
         },
         plugin::{PluginData, PluginTrait},
         web::auth,
     },
+    sidecar::SidecarMode,
+    signing::{SignaturePurpose, SignatureScheme, Signer},
     std::{
-        collections::HashMap,
+        collections::{HashMap, HashSet},
         path::{Path, PathBuf},
         pin::Pin,
-        str::FromStr,
         sync::{
             atomic::{AtomicU32, Ordering},
             Arc,
         },
     },
+    thumbnail::Thumbnailer,
 };
 
 pub struct Plugin {
@@ -56,8 +84,13 @@ pub struct Plugin {
     cache: RwLock<Cache<LocationIndexingCache>>,
     full_reload_remaining: AtomicU32,
     current_status: Arc<RwLock<ScanStatus>>,
-    signing_key: SigningKey<Sha256>,
-    verifying_key: VerifyingKey<Sha256>,
+    scan_errors: Arc<ScanErrorReport>,
+    signer: Arc<Signer>,
+    thumbnailer: Arc<Thumbnailer>,
+    event_options: Arc<EventOptions>,
+    watchers: Vec<RecommendedWatcher>,
+    watch_queue: watcher::WatchQueue,
+    next_full_scan: RwLock<DateTime<Utc>>,
 }
 
 #[derive(Debug)]
@@ -75,7 +108,35 @@ impl std::fmt::Display for ScanStatus {
     }
 }
 
-struct VerifyingKeyWrapper(pub VerifyingKey<Sha256>);
+struct DatabaseWrapper(pub Arc<Database>);
+
+/// How stored media becomes timeline events, shared by the timeline and the routes.
+struct EventOptions {
+    signature_lifetime: chrono::Duration,
+    keep_duplicates: bool,
+    duplicate_priority: Vec<String>,
+    near_duplicate_distance: u32,
+    best_of_near_duplicates: bool,
+}
+
+/// The configured location directories, the only places files are served from.
+struct MediaRoots(pub Vec<PathBuf>);
+
+/// Summaries of the scan errors of the last full scan, by location name.
+#[derive(Default)]
+struct ScanErrorReport(RwLock<HashMap<String, String>>);
+
+/// How many unreadable paths are listed in a scan error summary.
+const REPORTED_SCAN_ERRORS: usize = 10;
+
+const DEFAULT_SCAN_WORKERS: usize = 8;
+
+/// How many changed files are read and written to the database at once while scanning.
+const SCAN_BATCH_SIZE: usize = 500;
+
+/// The version of what the scan reads from a file, stored with every event. Raised whenever the
+/// scan learns to read more, so events of an older version are read again by the next full scan.
+const MEDIA_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize)]
 struct ConfigData {
@@ -83,18 +144,91 @@ struct ConfigData {
     pub interval: u32,
     pub full_reload_interval: Option<u32>,
     pub signing_key: RsaPrivateKey,
+    /// Keys that signed URLs before `signing_key` replaced them. They are only used to verify.
+    #[serde(default)]
+    pub previous_signing_keys: Vec<RsaPrivateKey>,
+    pub watch_interval: Option<u32>,
+    pub extensions: Option<Vec<String>>,
+    /// Minutes a signed media URL stays valid.
+    pub signature_lifetime: Option<u32>,
+    pub signature_scheme: Option<SignatureScheme>,
+    pub thumbnail_directory: Option<PathBuf>,
+    pub thumbnail_sizes: Option<Vec<u32>>,
+    /// Create thumbnails while scanning instead of on the first request.
+    #[serde(default)]
+    pub pregenerate_thumbnails: bool,
+    pub ffmpeg: Option<PathBuf>,
+    /// Create short clips of videos that play in the timeline instead of a still poster.
+    #[serde(default)]
+    pub video_previews: bool,
+    /// Show every copy of a file instead of collapsing copies with the same content into one event.
+    #[serde(default)]
+    pub keep_duplicates: bool,
+    /// Location names in the order their copy of a duplicate is shown, unlisted locations come last.
+    #[serde(default)]
+    pub duplicate_priority: Vec<String>,
+    /// Compute perceptual hashes of images while scanning, which finding near duplicates needs.
+    #[serde(default)]
+    pub perceptual_hashes: bool,
+    /// How many bits perceptual hashes of near duplicates may differ in.
+    pub near_duplicate_distance: Option<u32>,
+    /// Only show the best copy of near duplicate images in the timeline.
+    #[serde(default)]
+    pub best_of_near_duplicates: bool,
+    /// How many directories are listed and files are read at the same time while scanning.
+    pub scan_workers: Option<usize>,
 }
 
+const DEFAULT_SIGNATURE_LIFETIME: u32 = 24 * 60;
+
 #[derive(Serialize, Deserialize)]
 struct MediaLocation {
     location: PathBuf,
     #[serde(default)]
     name: String,
+    #[serde(default)]
+    watch: bool,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    extensions: Option<Vec<String>>,
+    kinds: Option<Vec<MediaKind>>,
+    #[serde(default)]
+    sidecar_mode: SidecarMode,
+    #[serde(skip)]
+    filter: ScanFilter,
 }
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 struct LocationIndexingCache {
-    timing_cache: HashMap<PathBuf, DateTime<Utc>>,
+    #[serde(default)]
+    inventory: HashMap<PathBuf, HashMap<String, FileFingerprint>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileFingerprint {
+    size: u64,
+    modified: DateTime<Utc>,
+    #[serde(default)]
+    inode: Option<u64>,
+    /// The latest modification of the sidecars of the file, see [`sidecar::sidecar_names`].
+    #[serde(default)]
+    sidecar_modified: Option<DateTime<Utc>>,
+}
+
+impl FileFingerprint {
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        FileFingerprint {
+            size: metadata.len(),
+            modified: metadata.modified().map(|v| v.into()).unwrap_or_default(),
+            #[cfg(unix)]
+            inode: Some(std::os::unix::fs::MetadataExt::ino(metadata)),
+            #[cfg(not(unix))]
+            inode: None,
+            sidecar_modified: None,
+        }
+    }
 }
 
 impl server_api::plugin::PluginTrait for Plugin {
@@ -110,6 +244,18 @@ impl server_api::plugin::PluginTrait for Plugin {
         .unwrap_or_else(|e| panic!("Unable to init media_scan plugin! Provided config does not fit the requirements: {}", e));
         for (name, instance) in config.locations.iter_mut() {
             instance.name = name.to_string();
+            let extensions: Vec<&str> =
+                match instance.extensions.as_ref().or(config.extensions.as_ref()) {
+                    Some(v) => v.iter().map(|v| v.as_str()).collect(),
+                    None => media_type::DEFAULT_EXTENSIONS.to_vec(),
+                };
+            let media_types: Vec<&'static MediaType> = extensions
+                .into_iter()
+                .map(|v| media_type::by_extension(v).unwrap_or_else(|| panic!("Unable to init media_scan plugin! Unknown media extension {} for location {}", v, name)))
+                .filter(|v| instance.kinds.as_ref().is_none_or(|kinds| kinds.contains(&v.kind)))
+                .collect();
+            instance.filter = ScanFilter::new(&instance.location, &instance.include, &instance.exclude, media_types, instance.sidecar_mode)
+                .unwrap_or_else(|e| panic!("Unable to init media_scan plugin! Invalid include/exclude patterns for location {}: {}", name, e));
         }
 
         let cache: Cache<LocationIndexingCache> =
@@ -120,8 +266,69 @@ impl server_api::plugin::PluginTrait for Plugin {
                 )
             });
 
-        let signing_key = SigningKey::new(config.signing_key.clone());
-        let verifying_key = signing_key.verifying_key();
+        let signer = Arc::new(Signer::new(
+            &config.signing_key,
+            &config.previous_signing_keys,
+            config.signature_scheme.unwrap_or_default(),
+        ));
+        let ffmpeg = thumbnail::find_ffmpeg(config.ffmpeg.as_deref()).unwrap_or_else(|e| {
+            data.report_error_string(format!(
+                "The Media Scan plugin is unable to run ffmpeg, videos get no posters: {}",
+                e
+            ));
+            None
+        });
+        let thumbnailer = Arc::new(Thumbnailer::new(
+            config.thumbnail_directory.clone().unwrap_or_else(|| {
+                std::env::temp_dir().join("timeline_plugin_media_scan_thumbnails")
+            }),
+            config
+                .thumbnail_sizes
+                .clone()
+                .unwrap_or(thumbnail::DEFAULT_SIZES.to_vec()),
+            ffmpeg,
+            config.video_previews,
+        ));
+
+        let event_options = Arc::new(EventOptions {
+            signature_lifetime: chrono::Duration::try_minutes(
+                config
+                    .signature_lifetime
+                    .unwrap_or(DEFAULT_SIGNATURE_LIFETIME) as i64,
+            )
+            .unwrap(),
+            keep_duplicates: config.keep_duplicates,
+            duplicate_priority: config.duplicate_priority.clone(),
+            near_duplicate_distance: config
+                .near_duplicate_distance
+                .unwrap_or(similarity::DEFAULT_DISTANCE),
+            best_of_near_duplicates: config.best_of_near_duplicates,
+        });
+
+        if let Err(e) = geo::create_index(&data.database).await {
+            data.report_error_string(format!(
+                "The Media Scan plugin is unable to create the geospatial index, location queries will be slow: {}",
+                e
+            ));
+        }
+        if let Err(e) = create_indexes(&data.database).await {
+            data.report_error_string(format!(
+                "The Media Scan plugin is unable to create its indexes, scans will be slow: {}",
+                e
+            ));
+        }
+
+        let watch_queue = watcher::WatchQueue::default();
+        let mut watchers = Vec::new();
+        for (name, location) in config.locations.iter().filter(|(_, v)| v.watch) {
+            match watcher::watch_location(name.clone(), &location.location, watch_queue.clone()) {
+                Ok(v) => watchers.push(v),
+                Err(e) => data.report_error_string(format!(
+                    "Unable to watch media location {:?}, falling back to periodic scans: {}",
+                    location.location, e
+                )),
+            }
+        }
 
         Plugin {
             plugin_data: data,
@@ -132,8 +339,13 @@ impl server_api::plugin::PluginTrait for Plugin {
             config,
             cache: RwLock::new(cache),
             current_status: Arc::new(RwLock::new(ScanStatus::Waiting(chrono::Utc::now()))),
-            signing_key,
-            verifying_key,
+            scan_errors: Arc::new(ScanErrorReport::default()),
+            signer,
+            thumbnailer,
+            event_options,
+            watchers,
+            watch_queue,
+            next_full_scan: RwLock::new(chrono::Utc::now()),
         }
     }
 
@@ -149,8 +361,24 @@ impl server_api::plugin::PluginTrait for Plugin {
     ) -> core::pin::Pin<Box<dyn futures::Future<Output = Option<chrono::Duration>> + Send + 'a>>
     {
         Box::pin(async move {
-            self.update_all_locations().await;
-            Some(chrono::Duration::try_minutes(self.config.interval as i64).unwrap())
+            let interval = chrono::Duration::try_minutes(self.config.interval as i64).unwrap();
+            if self.watchers.is_empty() {
+                self.update_all_locations().await;
+                return Some(interval);
+            }
+
+            // Watched locations are indexed incrementally, the full scan stays as a safety net.
+            let next_full_scan = *self.next_full_scan.read().await;
+            if chrono::Utc::now() >= next_full_scan {
+                self.update_all_locations().await;
+                *self.next_full_scan.write().await = chrono::Utc::now() + interval;
+            } else {
+                self.update_watched_paths().await;
+            }
+            Some(
+                chrono::Duration::try_seconds(self.config.watch_interval.unwrap_or(5) as i64)
+                    .unwrap(),
+            )
         })
     }
 
@@ -168,35 +396,50 @@ impl server_api::plugin::PluginTrait for Plugin {
             Database::generate_find_plugin_filter(AvailablePlugins::timeline_plugin_media_scan);
         let filter = Database::combine_documents(filter, plg_filter);
         let database = self.plugin_data.database.clone();
-        let singing_key = self.signing_key.clone();
+        let signer = self.signer.clone();
+        let thumbnailer = self.thumbnailer.clone();
+        let event_options = self.event_options.clone();
         Box::pin(async move {
-            let mut cursor = database.get_events::<Media>().find(filter, None).await?;
-            let mut result = Vec::new();
-            while let Some(v) = cursor.next().await {
-                let t = v?;
-                result.push(CompressedEvent {
-                    title: t.event.location_name,
-                    time: t.timing,
-                    data: serde_json::to_value(SignedMedia {
-                        signature: sign_string(&singing_key, &t.event.path),
-                        path: t.event.path,
-                    })
-                    .unwrap(),
-                })
-            }
-
-            Ok(result)
+            let events = database
+                .get_events::<Media>()
+                .find(filter, None)
+                .await?
+                .try_collect()
+                .await?;
+            Ok(shown_events(events, &event_options)
+                .into_iter()
+                .map(|v| signed_event(v, &signer, &thumbnailer, &event_options))
+                .collect())
         })
     }
 
     fn get_routes() -> Vec<rocket::Route> {
-        routes![get_file, get_status]
+        routes![
+            get_file,
+            get_thumbnail,
+            get_poster,
+            get_preview,
+            get_geo,
+            get_near_duplicates,
+            get_status
+        ]
     }
 
     fn rocket_build_access(&self, rocket: Rocket<Build>) -> Rocket<Build> {
         rocket
             .manage(self.current_status.clone())
-            .manage(VerifyingKeyWrapper(self.verifying_key.clone()))
+            .manage(self.scan_errors.clone())
+            .manage(self.signer.clone())
+            .manage(self.thumbnailer.clone())
+            .manage(self.event_options.clone())
+            .manage(DatabaseWrapper(self.plugin_data.database.clone()))
+            .manage(MediaRoots(
+                self.config
+                    .locations
+                    .values()
+                    .map(|v| v.location.clone())
+                    .collect(),
+            ))
     }
 }
 
@@ -204,51 +447,490 @@ impl server_api::plugin::PluginTrait for Plugin {
 async fn get_file(
     file: &str,
     signature: &str,
-    verifying_key: &State<VerifyingKeyWrapper>,
-) -> (Status, Option<Result<File, std::io::Error>>) {
-    if !verify_string(&verifying_key.inner().0, file, signature) {
-        return (Status::Unauthorized, None);
+    signer: &State<Arc<Signer>>,
+    database: &State<DatabaseWrapper>,
+    roots: &State<MediaRoots>,
+) -> Result<FileResponse, Status> {
+    signer.verify_media(SignaturePurpose::Full, file, signature)?;
+    let (media, path) = find_served_media(&database.inner().0, &roots.inner().0, file).await?;
+    let content_type = media_mime_type(&media)
+        .and_then(|v| ContentType::parse_flexible(&v))
+        .unwrap_or(ContentType::Binary);
+    // The status of the response depends on the requested range, so it is set by the responder.
+    FileResponse::open(&path, content_type)
+        .await
+        .map_err(|_| Status::NotFound)
+}
+
+#[get("/thumb/<size>/<file>/<signature>")]
+async fn get_thumbnail(
+    size: u32,
+    file: &str,
+    signature: &str,
+    signer: &State<Arc<Signer>>,
+    database: &State<DatabaseWrapper>,
+    roots: &State<MediaRoots>,
+    thumbnailer: &State<Arc<Thumbnailer>>,
+) -> Result<FileResponse, Status> {
+    signer.verify_media(SignaturePurpose::Thumbnail, file, signature)?;
+    if !thumbnailer.sizes().contains(&size) {
+        return Err(Status::NotFound);
     }
-    match PathBuf::from_str(file) {
-        Ok(v) => (Status::Ok, (Some(File::open(v).await))),
-        Err(_) => (Status::BadRequest, None),
+    let (media, path) = find_served_media(&database.inner().0, &roots.inner().0, file).await?;
+    if !media_mime_type(&media).is_some_and(|v| Thumbnailer::supports(&v)) {
+        return Err(Status::NotFound);
     }
+    let thumbnail = thumbnailer
+        .thumbnail(&path, file, size)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    FileResponse::open(&thumbnail, ContentType::JPEG)
+        .await
+        .map_err(|_| Status::NotFound)
 }
 
-#[get("/status")]
-async fn get_status(
+#[get("/poster/<file>/<signature>")]
+async fn get_poster(
+    file: &str,
+    signature: &str,
+    signer: &State<Arc<Signer>>,
+    database: &State<DatabaseWrapper>,
+    roots: &State<MediaRoots>,
+    thumbnailer: &State<Arc<Thumbnailer>>,
+) -> Result<FileResponse, Status> {
+    signer.verify_media(SignaturePurpose::Poster, file, signature)?;
+    let (media, path) = find_served_media(&database.inner().0, &roots.inner().0, file).await?;
+    if media.kind != Some(MediaKind::Video) || !thumbnailer.supports_posters() {
+        return Err(Status::NotFound);
+    }
+    let poster = thumbnailer
+        .poster(&path, file)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    FileResponse::open(&poster, ContentType::JPEG)
+        .await
+        .map_err(|_| Status::NotFound)
+}
+
+#[get("/preview/<file>/<signature>")]
+async fn get_preview(
+    file: &str,
+    signature: &str,
+    signer: &State<Arc<Signer>>,
+    database: &State<DatabaseWrapper>,
+    roots: &State<MediaRoots>,
+    thumbnailer: &State<Arc<Thumbnailer>>,
+) -> Result<FileResponse, Status> {
+    signer.verify_media(SignaturePurpose::Preview, file, signature)?;
+    let (media, path) = find_served_media(&database.inner().0, &roots.inner().0, file).await?;
+    if media.kind != Some(MediaKind::Video) || !thumbnailer.supports_previews() {
+        return Err(Status::NotFound);
+    }
+    let preview = thumbnailer
+        .preview(&path, file)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    FileResponse::open(&preview, ContentType::MP4)
+        .await
+        .map_err(|_| Status::NotFound)
+}
+
+/// A timeline event of an indexed file with signed URLs for everything the client can load.
+fn signed_event(
+    shown: ShownEvent,
+    signer: &Signer,
+    thumbnailer: &Thumbnailer,
+    options: &EventOptions,
+) -> CompressedEvent {
+    CompressedEvent {
+        title: shown.event.event.location_name.clone(),
+        time: shown.event.timing,
+        data: serde_json::to_value(signed_media(
+            shown.event.event,
+            shown.alternates,
+            shown.members,
+            signer,
+            thumbnailer,
+            options,
+        ))
+        .unwrap(),
+    }
+}
+
+fn signed_media(
+    media: Media,
+    alternates: Vec<Media>,
+    members: Vec<Media>,
+    signer: &Signer,
+    thumbnailer: &Thumbnailer,
+    options: &EventOptions,
+) -> SignedMedia {
+    let kind = media
+        .kind
+        .or_else(|| media_type::by_path(&paths::decode_path(&media.path)).map(|v| v.kind));
+    let sign = |purpose| signer.sign_media(purpose, &media.path, options.signature_lifetime);
+    let thumbnail_signature = media_mime_type(&media)
+        .filter(|v| Thumbnailer::supports(v))
+        .map(|_| sign(SignaturePurpose::Thumbnail));
+    let is_video = kind == Some(MediaKind::Video);
+    let poster_signature =
+        (is_video && thumbnailer.supports_posters()).then(|| sign(SignaturePurpose::Poster));
+    let preview_signature =
+        (is_video && thumbnailer.supports_previews()).then(|| sign(SignaturePurpose::Preview));
+    let (width, height) = media.details.display_size();
+    let (longitude, latitude) = match media.position {
+        Some(Geometry::Point { coordinates }) => (Some(coordinates[0]), Some(coordinates[1])),
+        None => (None, None),
+    };
+    SignedMedia {
+        signature: sign(SignaturePurpose::Full),
+        kind,
+        poster_signature,
+        preview_signature,
+        thumbnail_sizes: match thumbnail_signature {
+            Some(_) => thumbnailer.sizes().to_vec(),
+            None => Vec::new(),
+        },
+        thumbnail_signature,
+        size: media.size,
+        width,
+        height,
+        orientation: media.details.orientation,
+        duration: media.details.duration,
+        codec: media.details.codec,
+        latitude,
+        longitude,
+        altitude: media.altitude,
+        description: media.description,
+        alternates: alternates
+            .into_iter()
+            .map(|v| AlternateMedia {
+                path: v.path,
+                location_name: v.location_name,
+            })
+            .collect(),
+        group: media.group.map(|v| v.kind),
+        members: members
+            .into_iter()
+            .map(|v| signed_media(v, Vec::new(), Vec::new(), signer, thumbnailer, options))
+            .collect(),
+        path: media.path,
+    }
+}
+
+/// The events shown for stored events, with copies, near duplicates if configured and the files of
+/// Live Photos and bursts collapsed into one.
+fn shown_events(events: Vec<MediaEvent>, options: &EventOptions) -> Vec<ShownEvent> {
+    let events = collapse_duplicates(events, options);
+    let events = match options.best_of_near_duplicates {
+        true => collapse_near_duplicates(events, options),
+        false => events,
+    };
+    collapse_groups(events)
+}
+
+/// Collapses events of files with the same content into the copy of the preferred location,
+/// together with the other copies. The order of the events is kept.
+fn collapse_duplicates(events: Vec<MediaEvent>, options: &EventOptions) -> Vec<ShownEvent> {
+    if options.keep_duplicates {
+        return events.into_iter().map(ShownEvent::from).collect();
+    }
+    let rank = |media: &Media| {
+        let priority = options
+            .duplicate_priority
+            .iter()
+            .position(|v| *v == media.location_name)
+            .unwrap_or(usize::MAX);
+        (priority, media.path.clone())
+    };
+    let mut result: Vec<ShownEvent> = Vec::new();
+    let mut by_hash = HashMap::new();
+    for event in events {
+        let Some(hash) = event.event.content_hash.clone() else {
+            result.push(event.into());
+            continue;
+        };
+        let Some(index) = by_hash.get(&hash) else {
+            by_hash.insert(hash, result.len());
+            result.push(event.into());
+            continue;
+        };
+        let shown = &mut result[*index];
+        match rank(&event.event) < rank(&shown.event.event) {
+            true => shown
+                .alternates
+                .push(std::mem::replace(&mut shown.event, event).event),
+            false => shown.alternates.push(event.event),
+        }
+    }
+    result
+}
+
+/// Groups events of images that look the same, each group ordered from the best copy to the worst.
+/// Events without a perceptual hash are left out.
+fn near_duplicate_clusters(mut events: Vec<ShownEvent>, max_distance: u32) -> Vec<Vec<ShownEvent>> {
+    // The best copy of a group is the one the others are compared to.
+    events.sort_by_key(|v| std::cmp::Reverse(quality(&v.event.event)));
+    let (hashes, mut events): (Vec<u64>, Vec<Option<ShownEvent>>) = events
+        .into_iter()
+        .filter_map(|v| {
+            let hash = similarity::decode_hash(v.event.event.perceptual_hash.as_deref()?)?;
+            Some((hash, Some(v)))
+        })
+        .unzip();
+    similarity::clusters(&hashes, max_distance)
+        .into_iter()
+        .map(|cluster| {
+            cluster
+                .into_iter()
+                .filter_map(|i| events[i].take())
+                .collect()
+        })
+        .collect()
+}
+
+/// Orders copies of an image: more pixels first, then the larger file, which is usually the one
+/// that was compressed less.
+fn quality(media: &Media) -> (u64, u64) {
+    let pixels = media.details.width.unwrap_or(0) as u64 * media.details.height.unwrap_or(0) as u64;
+    (pixels, media.size)
+}
+
+/// Replaces every group of near duplicates with its best copy, the others become its alternates.
+/// The order of the events is kept.
+fn collapse_near_duplicates(events: Vec<ShownEvent>, options: &EventOptions) -> Vec<ShownEvent> {
+    let mut best = HashMap::new();
+    for cluster in near_duplicate_clusters(events.clone(), options.near_duplicate_distance) {
+        let mut cluster = cluster.into_iter();
+        let Some(shown) = cluster.next() else {
+            continue;
+        };
+        for v in cluster {
+            best.insert(v.event.id, shown.event.id.clone());
+        }
+    }
+    let mut result: Vec<ShownEvent> = Vec::new();
+    let mut hidden: HashMap<String, Vec<Media>> = HashMap::new();
+    for v in events {
+        match best.get(&v.event.id) {
+            Some(shown) => {
+                let hidden = hidden.entry(shown.clone()).or_default();
+                hidden.extend(v.alternates);
+                hidden.push(v.event.event);
+            }
+            None => result.push(v),
+        }
+    }
+    for v in result.iter_mut() {
+        v.alternates
+            .extend(hidden.remove(&v.event.id).unwrap_or_default());
+    }
+    result
+}
+
+/// Collapses the files of every Live Photo and burst into the event of the one shown for it: the
+/// still of a Live Photo and the first shot of a burst. The order of the events is kept.
+fn collapse_groups(events: Vec<ShownEvent>) -> Vec<ShownEvent> {
+    let rank = |media: &Media| {
+        (
+            media.kind != Some(MediaKind::Image),
+            media.time_modified,
+            media.path.clone(),
+        )
+    };
+    let mut result: Vec<ShownEvent> = Vec::new();
+    let mut by_group = HashMap::new();
+    for mut v in events {
+        let Some(group) = v.event.event.group.as_ref().map(|v| v.id.clone()) else {
+            result.push(v);
+            continue;
+        };
+        let Some(index) = by_group.get(&group) else {
+            by_group.insert(group, result.len());
+            result.push(v);
+            continue;
+        };
+        let shown = &mut result[*index];
+        if rank(&v.event.event) < rank(&shown.event.event) {
+            std::mem::swap(shown, &mut v);
+            shown.members.append(&mut v.members);
+        }
+        shown.alternates.append(&mut v.alternates);
+        shown.members.push(v.event.event);
+    }
+    for v in result.iter_mut() {
+        v.members.sort_by_key(rank);
+    }
+    result
+}
+
+/// The indexed media of a requested path and where it is on disk.
+async fn find_served_media(
+    database: &Database,
+    roots: &[PathBuf],
+    file: &str,
+) -> Result<(Media, PathBuf), Status> {
+    let media = find_indexed_media(database, file)
+        .await
+        .ok_or(Status::NotFound)?;
+    let path = resolve_served_path(roots, &paths::decode_path(file)).await?;
+    Ok((media, path))
+}
+
+/// The stored MIME type, or the one of the extension for media indexed before it was stored.
+fn media_mime_type(media: &Media) -> Option<String> {
+    match &media.mime_type {
+        Some(v) => Some(v.clone()),
+        None => {
+            media_type::by_path(&paths::decode_path(&media.path)).map(|v| v.mime_type.to_string())
+        }
+    }
+}
+
+async fn find_indexed_media(database: &Database, path: &str) -> Option<Media> {
+    database
+        .get_events::<Media>()
+        .find_one(
+            Database::combine_documents(
+                Database::generate_find_plugin_filter(AvailablePlugins::timeline_plugin_media_scan),
+                doc! { "id": path },
+            ),
+            None,
+        )
+        .await
+        .ok()
+        .flatten()
+        .map(|v| v.event)
+}
+
+/// Resolves symlinks and `..` of a requested path and makes sure the result is inside one of the
+/// media roots, which are resolved the same way.
+async fn resolve_served_path(roots: &[PathBuf], path: &Path) -> Result<PathBuf, Status> {
+    let path = fs::canonicalize(path).await.map_err(|_| Status::NotFound)?;
+    for root in roots {
+        if let Ok(root) = fs::canonicalize(root).await {
+            if path.starts_with(root) {
+                return Ok(path);
+            }
+        }
+    }
+    Err(Status::Forbidden)
+}
+
+/// Groups of images in a time range that look the same, the best copy first.
+#[allow(clippy::too_many_arguments)]
+#[get("/near_duplicates?<start>&<end>")]
+async fn get_near_duplicates(
+    start: &str,
+    end: &str,
     cookies: &CookieJar<'_>,
     config: &State<Config>,
-    current_status: &State<Arc<RwLock<ScanStatus>>>,
-) -> (Status, Option<String>) {
+    database: &State<DatabaseWrapper>,
+    signer: &State<Arc<Signer>>,
+    thumbnailer: &State<Arc<Thumbnailer>>,
+    event_options: &State<Arc<EventOptions>>,
+) -> Result<Json<Vec<Vec<CompressedEvent>>>, Status> {
     if auth(cookies, config).is_err() {
-        return (Status::Unauthorized, None);
+        return Err(Status::Unauthorized);
     }
-    let status = current_status.read().await;
-    (Status::Ok, Some(format!("{}", status)))
+    let filter = Database::combine_documents(
+        Database::generate_range_filter(&parse_time_range(start, end)?),
+        Database::combine_documents(
+            Database::generate_find_plugin_filter(AvailablePlugins::timeline_plugin_media_scan),
+            doc! { "event.perceptual_hash": { "$type": "string" } },
+        ),
+    );
+    let events = find_events(&database.0, filter).await?;
+    let events = collapse_duplicates(events, event_options);
+    let clusters = near_duplicate_clusters(events, event_options.near_duplicate_distance);
+    Ok(Json(
+        clusters
+            .into_iter()
+            .map(|cluster| {
+                cluster
+                    .into_iter()
+                    .map(|v| signed_event(v, signer, thumbnailer, event_options))
+                    .collect()
+            })
+            .collect(),
+    ))
 }
 
-fn sign_string(signing_key: &SigningKey<Sha256>, string: &str) -> String {
-    let mut rng = rand::thread_rng();
-    let signature = signing_key.sign_with_rng(&mut rng, string.as_bytes());
-    base64::prelude::BASE64_STANDARD.encode(signature.to_vec())
+/// The media captured in a time range inside of an area, either a bounding box as
+/// `west,south,east,north` or a radius in meters around `latitude,longitude`. Times are RFC 3339.
+#[allow(clippy::too_many_arguments)]
+#[get("/geo?<start>&<end>&<bbox>&<near>&<radius>")]
+async fn get_geo(
+    start: &str,
+    end: &str,
+    bbox: Option<&str>,
+    near: Option<&str>,
+    radius: Option<f64>,
+    cookies: &CookieJar<'_>,
+    config: &State<Config>,
+    database: &State<DatabaseWrapper>,
+    signer: &State<Arc<Signer>>,
+    thumbnailer: &State<Arc<Thumbnailer>>,
+    event_options: &State<Arc<EventOptions>>,
+) -> Result<Json<Vec<CompressedEvent>>, Status> {
+    if auth(cookies, config).is_err() {
+        return Err(Status::Unauthorized);
+    }
+    let range = parse_time_range(start, end)?;
+    let area = GeoArea::parse(bbox, near, radius).ok_or(Status::BadRequest)?;
+    let filter = Database::combine_documents(
+        Database::combine_documents(
+            Database::generate_range_filter(&range),
+            Database::generate_find_plugin_filter(AvailablePlugins::timeline_plugin_media_scan),
+        ),
+        area.filter(),
+    );
+    let events = find_events(&database.0, filter).await?;
+    Ok(Json(
+        shown_events(events, event_options)
+            .into_iter()
+            .map(|v| signed_event(v, signer, thumbnailer, event_options))
+            .collect(),
+    ))
 }
 
-fn verify_string(verifying_key: &VerifyingKey<Sha256>, string: &str, signature: &str) -> bool {
-    let bytes = match base64::prelude::BASE64_STANDARD.decode(signature) {
-        Ok(v) => v,
-        Err(_e) => return false,
+/// Parses the RFC 3339 bounds of a time range given to a route.
+fn parse_time_range(start: &str, end: &str) -> Result<TimeRange, Status> {
+    let parse_time = |v: &str| {
+        DateTime::parse_from_rfc3339(v)
+            .map(|v| v.with_timezone(&Utc))
+            .map_err(|_| Status::BadRequest)
     };
-    let bytes_slice: &[u8] = &bytes;
-    verifying_key
-        .verify(
-            string.as_bytes(),
-            &match Signature::try_from(bytes_slice) {
-                Ok(v) => v,
-                Err(_e) => return false,
-            },
-        )
-        .is_ok()
+    Ok(TimeRange {
+        start: parse_time(start)?,
+        end: parse_time(end)?,
+    })
+}
+
+async fn find_events(database: &Database, filter: Document) -> Result<Vec<MediaEvent>, Status> {
+    match database.get_events::<Media>().find(filter, None).await {
+        Ok(v) => v.try_collect().await,
+        Err(e) => Err(e),
+    }
+    .map_err(|_| Status::InternalServerError)
+}
+
+#[get("/status")]
+async fn get_status(
+    cookies: &CookieJar<'_>,
+    config: &State<Config>,
+    current_status: &State<Arc<RwLock<ScanStatus>>>,
+    scan_errors: &State<Arc<ScanErrorReport>>,
+) -> (Status, Option<String>) {
+    if auth(cookies, config).is_err() {
+        return (Status::Unauthorized, None);
+    }
+    let mut status = format!("{}", current_status.read().await);
+    for summary in scan_errors.0.read().await.values() {
+        status.push('\n');
+        status.push_str(summary);
+    }
+    (Status::Ok, Some(status))
 }
 
 impl Plugin {
@@ -275,7 +957,7 @@ impl Plugin {
                 let mut status = self.current_status.write().await;
                 *status = ScanStatus::Busy(name.clone());
             }
-            self.update_media_directory(name, &location.location, ignore_cache)
+            self.update_media_directory(name, &location.location, &location.filter, ignore_cache)
                 .await;
         }
 
@@ -283,67 +965,472 @@ impl Plugin {
         *status = ScanStatus::Waiting(chrono::Utc::now());
     }
 
-    async fn update_media_directory(&self, name: &str, location: &Path, full_reload: bool) {
-        let last_cache = self
-            .cache
-            .read()
+    /// Creates the thumbnails, posters and previews of new or changed media if that is done while
+    /// scanning.
+    async fn pregenerate_thumbnails(&self, media: &Media) {
+        let Some(kind) = media.kind else {
+            return;
+        };
+        if !self.config.pregenerate_thumbnails
+            || (kind == MediaKind::Image
+                && !media_mime_type(media).is_some_and(|v| Thumbnailer::supports(&v)))
+        {
+            return;
+        }
+        if let Err(e) = self
+            .thumbnailer
+            .pregenerate(&paths::decode_path(&media.path), &media.path, kind)
             .await
-            .get()
-            .timing_cache
-            .get(location)
-            .cloned();
-        let latest_time = match (full_reload, last_cache) {
-            (false, Some(v)) => v,
-            (false, None) => {
-                let mod_result = self.cache.write().await.modify::<Plugin>(|cache| {
-                    cache.timing_cache.insert(
-                        location.to_path_buf(),
-                        DateTime::from_timestamp_millis(0).unwrap(),
-                    ); //0 is a valid time-stamp
-                });
-                match mod_result {
-                    Ok(()) => {}
-                    Err(e) => {
-                        self.plugin_data
-                            .report_error_string(format!("Unable to save to cache: {}", e));
-                        return;
+        {
+            self.plugin_data.report_error_string(format!(
+                "The Media Scan plugin was unable to create thumbnails of {}: {}",
+                media.path, e
+            ));
+        }
+    }
+
+    /// Computes the perceptual hash of images the bundled decoders can read, if that is enabled.
+    async fn add_perceptual_hash(&self, media: &mut Media) {
+        if !self.config.perceptual_hashes
+            || media.kind != Some(MediaKind::Image)
+            || !media_mime_type(media).is_some_and(|v| Thumbnailer::supports(&v))
+        {
+            return;
+        }
+        match similarity::perceptual_hash(&paths::decode_path(&media.path)).await {
+            Ok(v) => media.perceptual_hash = Some(similarity::encode_hash(v)),
+            Err(e) => self.plugin_data.report_error_string(format!(
+                "The Media Scan plugin was unable to compute the perceptual hash of {}: {}",
+                media.path, e
+            )),
+        }
+    }
+
+    /// Reports the errors of a location scan and keeps their summary for the status endpoint.
+    async fn record_scan_errors(&self, name: &str, errors: &ScanErrors) {
+        let mut report = self.scan_errors.0.write().await;
+        if errors.is_empty() {
+            report.remove(name);
+            return;
+        }
+        let summary = summarize_scan_errors(name, errors);
+        self.plugin_data.report_error_string(format!(
+            "The Media Scan plugin was unable to scan parts of a location. {}",
+            summary
+        ));
+        report.insert(name.to_string(), summary);
+    }
+
+    async fn update_media_directory(
+        &self,
+        name: &str,
+        location: &Path,
+        filter: &ScanFilter,
+        full_reload: bool,
+    ) {
+        // Without a previous inventory (first scan, a cache of an older version or a full reload)
+        // every file is checked against the database instead.
+        let previous = match full_reload {
+            true => None,
+            false => self
+                .cache
+                .read()
+                .await
+                .get()
+                .inventory
+                .get(location)
+                .cloned(),
+        };
+        let mut inventory = HashMap::new();
+        let mut errors = ScanErrors::new();
+        let mut directories = HashSet::new();
+        let mut added = HashSet::new();
+        let mut success = true;
+
+        // Changed and new files are indexed in batches while the walk goes on, so their media never
+        // piles up in memory. Only the paths of new files are kept to find moved files at the end.
+        let (sender, mut receiver) = mpsc::channel(SCAN_BATCH_SIZE);
+        let walk = walk::walk_directory(
+            location,
+            filter,
+            &[],
+            self.scan_workers(),
+            sender,
+            &mut errors,
+        );
+        let index = async {
+            let mut changed = Vec::new();
+            loop {
+                let file = receiver.recv().await;
+                if let Some((path, fingerprint)) = &file {
+                    inventory.insert(path.clone(), *fingerprint);
+                    if previous.as_ref().and_then(|v| v.get(path)) != Some(fingerprint) {
+                        directories.insert(parent_directory(path));
+                        changed.push(path.clone());
                     }
                 }
-                *self
-                    .cache
-                    .read()
-                    .await
-                    .get()
-                    .timing_cache
-                    .get(location)
-                    .expect("Unable to cache: Probably an error inside the cache")
-                //we just updated the cache;
+                if changed.len() >= SCAN_BATCH_SIZE || (file.is_none() && !changed.is_empty()) {
+                    success &= self
+                        .index_changed(
+                            name,
+                            filter,
+                            previous.as_ref(),
+                            &inventory,
+                            std::mem::take(&mut changed),
+                            &mut added,
+                        )
+                        .await;
+                }
+                if file.is_none() {
+                    break;
+                }
             }
-            (true, _) => DateTime::from_timestamp_millis(0).unwrap(),
         };
-        let (media, new_latest_time) =
-            match recursive_directory_scan(name, location, &latest_time).await {
-                Ok(v) => v,
-                Err(e) => {
+        futures::future::join(walk, index).await;
+        self.record_scan_errors(name, &errors).await;
+        // Files that could not be reached this time keep their previous state, so they are
+        // neither re-read nor removed.
+        let unreadable = unreadable_paths(&errors);
+        if let Some(previous) = &previous {
+            for (path, fingerprint) in previous {
+                if !inventory.contains_key(path)
+                    && is_unreadable(&paths::decode_path(path), &unreadable)
+                {
+                    inventory.insert(path.clone(), *fingerprint);
+                }
+            }
+        }
+
+        let vanished = match &previous {
+            Some(previous) => {
+                let paths: Vec<&str> = previous
+                    .keys()
+                    .filter(|v| !inventory.contains_key(*v))
+                    .map(|v| v.as_str())
+                    .collect();
+                self.find_stored_media(&paths).await
+            }
+            None => {
+                self.find_location_media(name, |v| {
+                    !inventory.contains_key(&v.event.path)
+                        && !is_unreadable(&paths::decode_path(&v.event.path), &unreadable)
+                })
+                .await
+            }
+        };
+        let Some(vanished) = vanished else {
+            return;
+        };
+        if !vanished.is_empty() && inventory.is_empty() {
+            // An empty location is far more likely an unmounted drive than a deliberate wipe.
+            self.plugin_data.report_error_string(format!(
+                "The Media Scan plugin found no media in {:?}, but {} events are stored for it. Not removing them.",
+                location,
+                vanished.len()
+            ));
+            return;
+        }
+        directories.extend(vanished.iter().map(|v| parent_directory(&v.id)));
+        if !self.reconcile_moves(vanished, &added).await {
+            return;
+        }
+        success &= self.update_groups(directories).await;
+        if success {
+            self.cache
+                .write()
+                .await
+                .modify::<Plugin>(move |data| {
+                    data.inventory.insert(location.to_path_buf(), inventory);
+                })
+                .unwrap_or_else(|e| {
                     self.plugin_data.report_error_string(format!(
-                        "The Media Scan plugin was unable to scan a directory: {:?} \n Error: {}",
-                        location, e
+                        "Unable to save cache (media scan plugin): {e}"
                     ));
-                    return;
+                });
+        }
+    }
+
+    /// Indexes a batch of changed files found by a walk: modified files are read and replaced, new
+    /// files are read and inserted. New files are remembered in `added`, as they might turn out to
+    /// be vanished files that were moved.
+    async fn index_changed(
+        &self,
+        name: &str,
+        filter: &ScanFilter,
+        previous: Option<&HashMap<String, FileFingerprint>>,
+        inventory: &HashMap<String, FileFingerprint>,
+        changed: Vec<String>,
+        added: &mut HashSet<String>,
+    ) -> bool {
+        let paths: Vec<&str> = changed.iter().map(|v| v.as_str()).collect();
+        let Some(stored) = self.find_stored_media(&paths).await else {
+            return false;
+        };
+        let stored: HashMap<String, u32> = stored
+            .into_iter()
+            .map(|v| (v.id, v.event.version))
+            .collect();
+        let mut read = Vec::new();
+        for path in changed {
+            let known = previous.is_some_and(|v| v.contains_key(&path));
+            match (stored.get(&path), known) {
+                // Indexed before, but there is no inventory to tell whether it changed since. Only
+                // events of an older version are read again, to fill in what they lack.
+                (Some(version), false) if *version < MEDIA_VERSION => read.push(path),
+                (Some(_), false) => {}
+                (Some(_), true) => read.push(path),
+                (None, _) => {
+                    added.insert(path.clone());
+                    read.push(path);
                 }
-            };
-        let media: Vec<MediaEvent> = media
+            }
+        }
+        let mut media = self.read_media_batch(name, filter, inventory, &read).await;
+        self.hash_duplicate_candidates(&mut media).await;
+        let (modified, insert): (Vec<Media>, Vec<Media>) = media
             .into_iter()
-            .map(|media| Event {
-                timing: Timing::Instant(media.time_modified),
-                id: media.path.clone(),
-                plugin: Plugin::get_type(),
-                event: media,
+            .partition(|v| stored.contains_key(&v.path));
+        let mut success = true;
+        for media in modified {
+            success &= self.replace_media(media).await;
+        }
+        success &= self.insert_media(insert).await;
+        success
+    }
+
+    /// Reads the media of files, as many at the same time as there are scan workers. Files that
+    /// turn out not to be media are left out.
+    async fn read_media_batch(
+        &self,
+        name: &str,
+        filter: &ScanFilter,
+        inventory: &HashMap<String, FileFingerprint>,
+        files: &[String],
+    ) -> Vec<Media> {
+        // Owned paths, a closure over borrowed ones trips up the `Send` check of the request loop.
+        futures::stream::iter(files.iter().cloned())
+            .map(|path| async move {
+                let mut media =
+                    read_media(name, &paths::decode_path(&path), filter, &inventory[&path]).await?;
+                self.pregenerate_thumbnails(&media).await;
+                self.add_perceptual_hash(&mut media).await;
+                Some(media)
             })
+            .buffer_unordered(self.scan_workers())
+            .filter_map(std::future::ready)
+            .collect()
+            .await
+    }
+
+    fn scan_workers(&self) -> usize {
+        self.config
+            .scan_workers
+            .unwrap_or(DEFAULT_SCAN_WORKERS)
+            .max(1)
+    }
+
+    /// Computes the content hash of media whose size and sample hash match another file, indexed
+    /// or in the same batch, and of the indexed files it matches.
+    async fn hash_duplicate_candidates(&self, media: &mut [Media]) {
+        let hashes: HashSet<&str> = media
+            .iter()
+            .filter_map(|v| v.sample_hash.as_deref())
             .collect();
+        let hashes: Vec<&str> = hashes.into_iter().collect();
+        let mut stored = Vec::new();
+        for hashes in hashes.chunks(1000) {
+            let Some(mut found) = self
+                .find_media(doc! {
+                    "event.sample_hash": {
+                        "$in": hashes
+                    }
+                })
+                .await
+            else {
+                return;
+            };
+            stored.append(&mut found);
+        }
+        // The stored versions of the batch are about to be replaced.
+        let batch: HashSet<String> = media.iter().map(|v| v.path.clone()).collect();
+        stored.retain(|v| !batch.contains(&v.id));
+        // Moved files are indexed before their vanished events are re-pointed to them, so a twin
+        // that is gone is most likely the file itself and not worth reading the whole file for.
+        let mut present = Vec::with_capacity(stored.len());
+        for event in stored {
+            if fs::try_exists(paths::decode_path(&event.event.path))
+                .await
+                .unwrap_or(true)
+            {
+                present.push(event);
+            }
+        }
+        let stored = present;
+
+        let mut counts: HashMap<(u64, String), usize> = HashMap::new();
+        for v in media.iter().chain(stored.iter().map(|v| &v.event)) {
+            if let Some(hash) = &v.sample_hash {
+                *counts.entry((v.size, hash.clone())).or_default() += 1;
+            }
+        }
+        let collides = |v: &Media| {
+            v.sample_hash
+                .as_ref()
+                .is_some_and(|hash| counts.get(&(v.size, hash.clone())) > Some(&1))
+        };
+
+        for media in media.iter_mut().filter(|v| collides(v)) {
+            media.content_hash = self.content_hash(&media.path).await;
+        }
+        for event in stored
+            .iter()
+            .filter(|v| v.event.content_hash.is_none() && collides(&v.event))
+        {
+            // New files are indexed before vanished ones are removed, so a twin might be gone.
+            let hash = match hashing::content_hash(&paths::decode_path(&event.event.path)).await {
+                Ok(v) => v,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    self.plugin_data.report_error_string(format!(
+                        "The Media Scan plugin was unable to hash {}: {}",
+                        event.event.path, e
+                    ));
+                    continue;
+                }
+            };
+            if let Err(e) = self
+                .plugin_data
+                .database
+                .get_events::<Media>()
+                .update_one(
+                    Database::combine_documents(
+                        Database::generate_find_plugin_filter(
+                            AvailablePlugins::timeline_plugin_media_scan,
+                        ),
+                        doc! {
+                            "id": &event.id
+                        },
+                    ),
+                    doc! {
+                        "$set": {
+                            "event.content_hash": hash
+                        }
+                    },
+                    None,
+                )
+                .await
+            {
+                self.plugin_data.report_error_string(format!(
+                    "Unable to store the content hash of {}: {}",
+                    event.id, e
+                ));
+            }
+        }
+    }
+
+    /// Detects the Live Photos and bursts of directories again and stores the groups that changed.
+    async fn update_groups(&self, directories: HashSet<String>) -> bool {
+        let mut success = true;
+        for directory in directories {
+            let Some(events) = self.find_media(directory_filter(&directory)).await else {
+                success = false;
+                continue;
+            };
+            let media: Vec<&Media> = events.iter().map(|v| &v.event).collect();
+            let groups = groups::detect_groups(&media);
+            for event in events.iter() {
+                let group = groups.get(&event.event.path);
+                if group == event.event.group.as_ref() {
+                    continue;
+                }
+                if let Err(e) = self
+                    .plugin_data
+                    .database
+                    .get_events::<Media>()
+                    .update_one(
+                        Database::combine_documents(
+                            Database::generate_find_plugin_filter(
+                                AvailablePlugins::timeline_plugin_media_scan,
+                            ),
+                            doc! {
+                                "id": &event.id
+                            },
+                        ),
+                        doc! {
+                            "$set": {
+                                "event.group": to_bson(&group).unwrap_or(Bson::Null)
+                            }
+                        },
+                        None,
+                    )
+                    .await
+                {
+                    self.plugin_data.report_error_string(format!(
+                        "Unable to store the group of {}: {}",
+                        event.id, e
+                    ));
+                    success = false;
+                }
+            }
+        }
+        success
+    }
+
+    async fn content_hash(&self, path: &str) -> Option<String> {
+        match hashing::content_hash(&paths::decode_path(path)).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.plugin_data.report_error_string(format!(
+                    "The Media Scan plugin was unable to hash {}: {}",
+                    path, e
+                ));
+                None
+            }
+        }
+    }
+
+    async fn find_stored_media(&self, paths: &[&str]) -> Option<Vec<MediaEvent>> {
+        let mut result = Vec::new();
+        // Keeps the query documents well below the BSON size limit on large locations.
+        for paths in paths.chunks(1000) {
+            let mut found = self
+                .find_media(doc! {
+                    "id": {
+                        "$in": paths
+                    }
+                })
+                .await?;
+            result.append(&mut found);
+        }
+        Some(result)
+    }
+
+    /// The media of a location that `keep` accepts. Events are checked while they are read, so the
+    /// whole location never has to fit into memory.
+    async fn find_location_media(
+        &self,
+        name: &str,
+        keep: impl Fn(&MediaEvent) -> bool,
+    ) -> Option<Vec<MediaEvent>> {
+        self.find_media_where(
+            doc! {
+                "event.location_name": name
+            },
+            keep,
+        )
+        .await
+    }
+
+    async fn find_media(&self, filter: Document) -> Option<Vec<MediaEvent>> {
+        self.find_media_where(filter, |_| true).await
+    }
 
-        let paths: Vec<&str> = media.iter().map(|v| v.event.path.as_str()).collect();
-        let already_found_media: Vec<MediaEvent> = match self
+    async fn find_media_where(
+        &self,
+        filter: Document,
+        keep: impl Fn(&MediaEvent) -> bool,
+    ) -> Option<Vec<MediaEvent>> {
+        match self
             .plugin_data
             .database
             .get_events()
@@ -352,24 +1439,24 @@ impl Plugin {
                     Database::generate_find_plugin_filter(
                         AvailablePlugins::timeline_plugin_media_scan,
                     ),
-                    doc! {
-                        "event.path": {
-                            "$in": paths
-                        }
-                    },
+                    filter,
                 ),
                 None,
             )
             .await
         {
-            Ok(v) => match v.try_collect().await {
-                Ok(v) => v,
+            Ok(v) => match v
+                .try_filter(|v| std::future::ready(keep(v)))
+                .try_collect()
+                .await
+            {
+                Ok(v) => Some(v),
                 Err(e) => {
                     self.plugin_data.report_error_string(format!(
                         "Unable to collect all matching paths: {}",
                         e
                     ));
-                    return;
+                    None
                 }
             },
             Err(e) => {
@@ -377,135 +1464,719 @@ impl Plugin {
                     "Error fetching already found media from database: {}",
                     e
                 ));
-                return;
+                None
             }
-        };
+        }
+    }
 
-        let mut insert: Vec<MediaEvent> = Vec::new();
+    async fn insert_media(&self, media: Vec<Media>) -> bool {
+        if media.is_empty() {
+            return true;
+        }
+        let insert: Vec<MediaEvent> = media.into_iter().map(media_event).collect();
+        match self.plugin_data.database.register_events(&insert).await {
+            Ok(_t) => true,
+            Err(e) => {
+                self.plugin_data
+                    .report_error_string(format!("Unable to add MediaEvent to Database: {}", e));
+                false
+            }
+        }
+    }
 
-        for media in media {
-            let mut found = false;
-            for existing in already_found_media.iter() {
-                if existing.id == media.id {
-                    found = true;
-                    break;
+    async fn replace_media(&self, media: Media) -> bool {
+        let event = media_event(media);
+        match self
+            .plugin_data
+            .database
+            .get_events::<Media>()
+            .replace_one(
+                Database::combine_documents(
+                    Database::generate_find_plugin_filter(
+                        AvailablePlugins::timeline_plugin_media_scan,
+                    ),
+                    doc! {
+                        "id": &event.id
+                    },
+                ),
+                &event,
+                None,
+            )
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                self.plugin_data.report_error_string(format!(
+                    "Unable to update modified media {}: {}",
+                    event.id, e
+                ));
+                false
+            }
+        }
+    }
+
+    async fn remove_media(&self, filter: Document) -> bool {
+        match self
+            .plugin_data
+            .database
+            .get_events::<Media>()
+            .delete_many(
+                Database::combine_documents(
+                    Database::generate_find_plugin_filter(
+                        AvailablePlugins::timeline_plugin_media_scan,
+                    ),
+                    filter,
+                ),
+                None,
+            )
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                self.plugin_data.report_error_string(format!(
+                    "Unable to remove vanished media from database: {}",
+                    e
+                ));
+                false
+            }
+        }
+    }
+
+    async fn update_watched_paths(&self) {
+        let changes = watcher::drain(&self.watch_queue);
+        if changes.is_empty() {
+            return;
+        }
+        for (name, paths) in changes {
+            let Some(location) = self.config.locations.get(&name) else {
+                continue;
+            };
+            {
+                let mut status = self.current_status.write().await;
+                *status = ScanStatus::Busy(format!("{} ({} changed paths)", name, paths.len()));
+            }
+            self.update_paths(&name, &location.location, &location.filter, paths)
+                .await;
+        }
+
+        let mut status = self.current_status.write().await;
+        *status = ScanStatus::Waiting(chrono::Utc::now());
+    }
+
+    /// Indexes paths reported by a watcher: new or modified files are (re-)indexed, new directories
+    /// are scanned and removed paths lose their events.
+    async fn update_paths(
+        &self,
+        name: &str,
+        location: &Path,
+        filter: &ScanFilter,
+        paths: HashSet<PathBuf>,
+    ) {
+        let mut inventory = HashMap::new();
+        let mut errors = ScanErrors::new();
+        let mut removed = Vec::new();
+        let mut paths = paths;
+        if filter.sidecar_mode() != SidecarMode::Ignore {
+            // An edited sidecar changes what is known about the media it describes.
+            let sidecars: Vec<PathBuf> = paths
+                .iter()
+                .filter(|v| sidecar::is_sidecar(v))
+                .cloned()
+                .collect();
+            for sidecar in sidecars {
+                if let Ok(media) = task::spawn_blocking(move || sidecar::find_media(&sidecar)).await
+                {
+                    paths.extend(media);
                 }
             }
-            if !found {
-                insert.push(media)
-            }
-        }
-        if !insert.is_empty() {
-            match self.plugin_data.database.register_events(&insert).await {
-                Ok(_t) => {
-                    self.cache
-                        .write()
-                        .await
-                        .modify::<Plugin>(move |data| {
-                            data.timing_cache
-                                .insert(location.to_path_buf(), new_latest_time);
-                        })
-                        .unwrap_or_else(|e| {
-                            self.plugin_data.report_error_string(format!(
-                                "Unable to save cache (media scan plugin): {e}"
-                            ));
-                        });
+        }
+        for path in paths {
+            if !path.starts_with(location) {
+                continue;
+            }
+            let metadata = match fs::metadata(&path).await {
+                Ok(v) => v,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    removed.push(paths::encode_path(&path));
+                    continue;
                 }
                 Err(e) => {
+                    errors.push((path, e));
+                    continue;
+                }
+            };
+            if filter.is_path_excluded(&path, metadata.is_dir()).await {
+                continue;
+            }
+            if metadata.is_dir() {
+                let ignores = filter.ignores_for(path.parent().unwrap_or(location)).await;
+                recursive_directory_scan(
+                    &path,
+                    filter,
+                    &ignores,
+                    self.scan_workers(),
+                    &mut inventory,
+                    &mut errors,
+                )
+                .await;
+            } else if metadata.is_file() && filter.is_candidate(&path) {
+                let mut fingerprint = FileFingerprint::from_metadata(&metadata);
+                if filter.sidecar_mode() != SidecarMode::Ignore {
+                    let path = path.clone();
+                    fingerprint.sidecar_modified =
+                        task::spawn_blocking(move || sidecar::find_latest_modified(&path))
+                            .await
+                            .unwrap_or_default();
+                }
+                inventory.insert(paths::encode_path(&path), fingerprint);
+            }
+        }
+        if !errors.is_empty() {
+            self.plugin_data.report_error_string(format!(
+                "The Media Scan plugin was unable to read changed media. {}",
+                summarize_scan_errors(name, &errors)
+            ));
+        }
+
+        // A removed path might have been a directory, so everything below it vanished as well.
+        let vanished = match removed.is_empty() {
+            true => Vec::new(),
+            false => {
+                let mut conditions = vec![doc! { "id": { "$in": &removed } }];
+                // Everything from `path/` up to the character after `/`.
+                conditions.extend(removed.iter().map(
+                    |v| doc! { "id": { "$gte": format!("{}/", v), "$lt": format!("{}0", v) } },
+                ));
+                let Some(vanished) = self
+                    .find_media(doc! {
+                        "$or": conditions
+                    })
+                    .await
+                else {
+                    return;
+                };
+                vanished
+            }
+        };
+
+        // A rename arrives as the removal of the old path and the creation of the new one, so new
+        // files are remembered to re-point the events of the removed ones to them.
+        let mut success = true;
+        let mut added = HashSet::new();
+        let files: Vec<String> = inventory.keys().cloned().collect();
+        for batch in files.chunks(SCAN_BATCH_SIZE) {
+            let paths: Vec<&str> = batch.iter().map(|v| v.as_str()).collect();
+            let Some(stored) = self.find_stored_media(&paths).await else {
+                return;
+            };
+            let stored: HashSet<String> = stored.into_iter().map(|v| v.id).collect();
+            added.extend(batch.iter().filter(|v| !stored.contains(*v)).cloned());
+            let mut changed = self.read_media_batch(name, filter, &inventory, batch).await;
+            self.hash_duplicate_candidates(&mut changed).await;
+            let (modified, insert): (Vec<Media>, Vec<Media>) =
+                changed.into_iter().partition(|v| stored.contains(&v.path));
+            for media in modified {
+                success &= self.replace_media(media).await;
+            }
+            success &= self.insert_media(insert).await;
+        }
+        let directories = inventory
+            .keys()
+            .chain(vanished.iter().map(|v| &v.id))
+            .map(|v| parent_directory(v))
+            .collect();
+        success &= self.reconcile_moves(vanished, &added).await;
+        success &= self.update_groups(directories).await;
+
+        if success {
+            self.cache
+                .write()
+                .await
+                .modify::<Plugin>(move |data| {
+                    let location_inventory =
+                        data.inventory.entry(location.to_path_buf()).or_default();
+                    for path in removed {
+                        let directory = format!("{}/", path);
+                        location_inventory.retain(|v, _| *v != path && !v.starts_with(&directory));
+                    }
+                    location_inventory.extend(inventory);
+                })
+                .unwrap_or_else(|e| {
                     self.plugin_data.report_error_string(format!(
-                        "Unable to add MediaEvent to Database: {}",
-                        e
+                        "Unable to save cache (media scan plugin): {e}"
                     ));
+                });
+        }
+    }
+
+    /// Re-points events of vanished files to the files `added` by a scan with the same size and
+    /// sample hash, and the same content hash if one is known, and removes the remaining vanished
+    /// events. Added files were indexed already, their new events give way to the re-pointed ones.
+    async fn reconcile_moves(&self, vanished: Vec<MediaEvent>, added: &HashSet<String>) -> bool {
+        if vanished.is_empty() {
+            return true;
+        }
+
+        let hashes: Vec<&str> = vanished
+            .iter()
+            .filter_map(|v| v.event.sample_hash.as_deref())
+            .collect();
+        let mut candidates = Vec::new();
+        for hashes in hashes.chunks(1000) {
+            let Some(found) = self
+                .find_media(doc! {
+                    "event.sample_hash": {
+                        "$in": hashes
+                    }
+                })
+                .await
+            else {
+                return false;
+            };
+            candidates.extend(found.into_iter().filter(|v| added.contains(&v.id)));
+        }
+        let mut remove = Vec::new();
+        for event in vanished {
+            // Files with a content hash had a twin with the same sample hash, so the sample hash
+            // alone could swap them. Those are only moves if the whole content matches.
+            let mut moved_to = None;
+            for (index, candidate) in candidates.iter_mut().enumerate() {
+                if event.event.sample_hash.is_none()
+                    || candidate.event.sample_hash != event.event.sample_hash
+                    || candidate.event.size != event.event.size
+                {
+                    continue;
+                }
+                if let Some(hash) = &event.event.content_hash {
+                    if candidate.event.content_hash.is_none() {
+                        candidate.event.content_hash = self.content_hash(&candidate.id).await;
+                    }
+                    if candidate.event.content_hash.as_ref() != Some(hash) {
+                        continue;
+                    }
                 }
+                moved_to = Some(index);
+                break;
+            }
+            let Some(index) = moved_to else {
+                remove.push(event.id);
+                continue;
+            };
+            let path = candidates.remove(index).id;
+            if !self.remove_media(doc! { "id": &path }).await {
+                return false;
+            }
+            if let Err(e) = self
+                .plugin_data
+                .database
+                .get_events::<Media>()
+                .update_one(
+                    Database::combine_documents(
+                        Database::generate_find_plugin_filter(
+                            AvailablePlugins::timeline_plugin_media_scan,
+                        ),
+                        doc! {
+                            "id": &event.id
+                        },
+                    ),
+                    doc! {
+                        "$set": {
+                            "id": &path,
+                            "event.path": &path
+                        }
+                    },
+                    None,
+                )
+                .await
+            {
+                self.plugin_data.report_error_string(format!(
+                    "Unable to update moved media {} -> {}: {}",
+                    event.id, path, e
+                ));
+                return false;
             }
         }
+
+        remove.is_empty()
+            || self
+                .remove_media(doc! {
+                    "id": {
+                        "$in": remove
+                    }
+                })
+                .await
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Media {
     path: String,
     time_modified: DateTime<Utc>,
     location_name: String,
+    #[serde(default)]
+    time_source: TimeSource,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    sample_hash: Option<String>,
+    /// BLAKE3 of the content, only present if another file has the same size and sample hash.
+    #[serde(default)]
+    content_hash: Option<String>,
+    /// Difference hash of images, see [`similarity::perceptual_hash`].
+    #[serde(default)]
+    perceptual_hash: Option<String>,
+    #[serde(default)]
+    kind: Option<MediaKind>,
+    #[serde(default)]
+    mime_type: Option<String>,
+    #[serde(default)]
+    details: MediaDetails,
+    /// Where the file was captured. Left out if unknown, so only located media is indexed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<Geometry>,
+    /// In meters above sea level.
+    #[serde(default)]
+    altitude: Option<f64>,
+    #[serde(default)]
+    identifiers: CaptureIdentifiers,
+    #[serde(default)]
+    description: Option<String>,
+    /// The Live Photo or burst the file belongs to, set once its whole directory was scanned.
+    #[serde(default)]
+    group: Option<MediaGroup>,
+    /// The [`MEDIA_VERSION`] the file was read with, 0 for events from before versions existed.
+    #[serde(default)]
+    version: u32,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Technical details read during the scan, as far as the format of a file is understood.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MediaDetails {
+    /// The stored size in pixels, before the orientation is applied.
+    width: Option<u32>,
+    height: Option<u32>,
+    /// The EXIF orientation from 1 to 8. Rotated videos get the value of the same rotation.
+    orientation: Option<u16>,
+    /// In milliseconds.
+    duration: Option<u64>,
+    codec: Option<String>,
+}
+
+impl MediaDetails {
+    /// The size as displayed, with width and height swapped if the orientation turns the image on
+    /// its side.
+    fn display_size(&self) -> (Option<u32>, Option<u32>) {
+        match self.orientation {
+            Some(5..=8) => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum TimeSource {
+    Exif,
+    ExifLocalTime,
+    Container,
+    ContainerLocalTime,
+    Sidecar,
+    SidecarLocalTime,
+    #[default]
+    FileModified,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureTime {
+    time: DateTime<Utc>,
+    source: TimeSource,
+}
+
+/// Everything read from the content of a file during the scan.
+#[derive(Debug, Default)]
+pub struct FileMetadata {
+    creation_time: Option<CaptureTime>,
+    details: MediaDetails,
+    position: Option<GpsPosition>,
+    identifiers: CaptureIdentifiers,
+    description: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct SignedMedia {
     path: String,
     signature: String,
+    kind: Option<MediaKind>,
+    thumbnail_signature: Option<String>,
+    thumbnail_sizes: Vec<u32>,
+    poster_signature: Option<String>,
+    preview_signature: Option<String>,
+    size: u64,
+    /// The size as displayed, with the orientation applied.
+    width: Option<u32>,
+    height: Option<u32>,
+    orientation: Option<u16>,
+    duration: Option<u64>,
+    codec: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    altitude: Option<f64>,
+    description: Option<String>,
+    /// Other copies of the same file or picture that are not shown as events of their own.
+    alternates: Vec<AlternateMedia>,
+    group: Option<GroupKind>,
+    /// The other files of the Live Photo or burst, in the order they were taken.
+    members: Vec<SignedMedia>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AlternateMedia {
+    path: String,
+    location_name: String,
 }
 
 type MediaEvent = Event<Media>;
 
-const SUPPORTED_EXTENSIONS: [&str; 12] = [
-    "png", "jpg", "mp4", "mkv", "webm", "jpeg", "mov", "heic", "gif", "mp3", "opus", "m4a",
-];
+/// An event together with the files collapsed into it.
+#[derive(Clone)]
+struct ShownEvent {
+    event: MediaEvent,
+    /// Copies of the same file or picture.
+    alternates: Vec<Media>,
+    /// The other files of its Live Photo or burst.
+    members: Vec<Media>,
+}
+
+impl From<MediaEvent> for ShownEvent {
+    fn from(event: MediaEvent) -> Self {
+        ShownEvent {
+            event,
+            alternates: Vec::new(),
+            members: Vec::new(),
+        }
+    }
+}
 
+/// Walks a directory and adds every candidate file to `inventory`. Entries that can not be read
+/// are collected in `errors` instead of aborting the walk.
 pub async fn recursive_directory_scan(
+    path: &Path,
+    filter: &ScanFilter,
+    ignores: &[Arc<Gitignore>],
+    workers: usize,
+    inventory: &mut HashMap<String, FileFingerprint>,
+    errors: &mut ScanErrors,
+) {
+    let (sender, mut receiver) = mpsc::channel(SCAN_BATCH_SIZE);
+    let walk = walk::walk_directory(path, filter, ignores, workers, sender, errors);
+    let collect = async {
+        while let Some((path, fingerprint)) = receiver.recv().await {
+            inventory.insert(path, fingerprint);
+        }
+    };
+    futures::future::join(walk, collect).await;
+}
+
+/// Paths that could not be read during a scan together with the reason.
+pub type ScanErrors = Vec<(PathBuf, std::io::Error)>;
+
+/// The paths that could not be read, to look up with [`is_unreadable`].
+fn unreadable_paths(errors: &ScanErrors) -> HashSet<&Path> {
+    errors.iter().map(|(v, _)| v.as_path()).collect()
+}
+
+/// Whether a path is, or is inside of, a path that could not be read.
+fn is_unreadable(path: &Path, unreadable: &HashSet<&Path>) -> bool {
+    !unreadable.is_empty() && path.ancestors().any(|v| unreadable.contains(v))
+}
+
+/// A summary of scan errors with the first [`REPORTED_SCAN_ERRORS`] paths.
+fn summarize_scan_errors(location_name: &str, errors: &ScanErrors) -> String {
+    let mut summary = format!(
+        "{} paths of {} could not be read:",
+        errors.len(),
+        location_name
+    );
+    for (path, error) in errors.iter().take(REPORTED_SCAN_ERRORS) {
+        summary.push_str(&format!("\n {:?}: {}", path, error));
+    }
+    if errors.len() > REPORTED_SCAN_ERRORS {
+        summary.push_str(&format!(
+            "\n ... and {} more",
+            errors.len() - REPORTED_SCAN_ERRORS
+        ));
+    }
+    summary
+}
+
+async fn read_media(
     location_name: &str,
     path: &Path,
-    current_newest: &DateTime<Utc>,
-) -> Result<(Vec<Media>, DateTime<Utc>), std::io::Error> {
-    let mut found_media = Vec::new();
-    let mut updated_newest = *current_newest;
-    let mut dir = fs::read_dir(path).await?;
-    let mut next_result = dir.next_entry().await;
-    while let Ok(Some(ref entry)) = next_result {
-        let file_type = entry.file_type().await?;
-        if file_type.is_dir() {
-            let (mut found_media_recusion, updated_time) = Box::pin(recursive_directory_scan(
-                location_name,
-                &entry.path(),
-                current_newest,
-            ))
-            .await?;
-            found_media.append(&mut found_media_recusion);
-            if updated_time > updated_newest {
-                updated_newest = updated_time;
-            }
-        } else if file_type.is_file() {
-            if let Some(ex) = entry.path().extension() {
-                match ex.to_str() {
-                    Some(ex) => {
-                        if SUPPORTED_EXTENSIONS.contains(&ex.to_lowercase().as_str()) {
-                            let file_creation_time = match File::open(entry.path())
-                                .await?
-                                .metadata()
-                                .await?
-                                .modified()
-                            {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    eprintln!(
-                                        "Unable to find creation time for file: {:?}\nError: {}",
-                                        entry.path(),
-                                        e
-                                    );
-                                    continue;
-                                }
-                            };
-                            let creation_time: DateTime<Utc> = file_creation_time.into();
-                            if &creation_time > current_newest {
-                                found_media.push(Media {
-                                    path: entry.path().to_str().unwrap_or("default").to_string(),
-                                    time_modified: creation_time,
-                                    location_name: location_name.to_string(),
-                                });
-                                if creation_time > updated_newest {
-                                    updated_newest = creation_time;
-                                }
-                            }
-                        }
-                    }
-                    None => {
-                        eprint!("The Media Scan plugin encountered an issue dealing with a filesystem file extension.");
-                    }
-                }
-            }
+    filter: &ScanFilter,
+    fingerprint: &FileFingerprint,
+) -> Option<Media> {
+    let media_type = detect_media_type(path, filter).await?;
+    let mut metadata = read_metadata(path.to_path_buf(), media_type).await;
+    let mode = filter.sidecar_mode();
+    if mode != SidecarMode::Ignore {
+        let path = path.to_path_buf();
+        if let Ok(Some(sidecar)) = task::spawn_blocking(move || sidecar::read_sidecars(&path)).await
+        {
+            sidecar.apply(&mut metadata, mode);
         }
+    }
+    let capture_time = metadata.creation_time.unwrap_or(CaptureTime {
+        time: fingerprint.modified,
+        source: TimeSource::FileModified,
+    });
+    Some(Media {
+        path: paths::encode_path(path),
+        time_modified: capture_time.time,
+        location_name: location_name.to_string(),
+        time_source: capture_time.source,
+        size: fingerprint.size,
+        sample_hash: hashing::sample_hash(path).await.ok(),
+        content_hash: None,
+        perceptual_hash: None,
+        kind: Some(media_type.kind),
+        mime_type: Some(media_type.mime_type.to_string()),
+        details: metadata.details,
+        position: metadata.position.map(Geometry::from),
+        altitude: metadata.position.and_then(|v| v.altitude),
+        identifiers: metadata.identifiers,
+        description: metadata.description,
+        group: None,
+        version: MEDIA_VERSION,
+    })
+}
+
+/// Detects the type of a file from its content and whether the location indexes it. A file is
+/// indexed if the type of its extension or of its content is configured, files without an extension
+/// also if their content belongs to the family of a configured type (e.g. HEIF for HEIC).
+async fn detect_media_type(path: &Path, filter: &ScanFilter) -> Option<&'static MediaType> {
+    let by_extension = media_type::by_path(path);
+    let mut header = Vec::with_capacity(media_type::SNIFF_LENGTH);
+    match File::open(path).await {
+        Ok(file) => {
+            file.take(media_type::SNIFF_LENGTH as u64)
+                .read_to_end(&mut header)
+                .await
+                .ok()?;
+        }
+        Err(_) => return by_extension.filter(|v| filter.allows(v)),
+    }
+    let sniffed = media_type::sniff(&header, by_extension).or(by_extension)?;
+    if filter.allows(sniffed) || by_extension.is_some_and(|v| filter.allows(v)) {
+        return Some(sniffed);
+    }
+    if path.extension().is_some() {
+        return None;
+    }
+    filter
+        .media_types()
+        .iter()
+        .find(|v| media_type::sniff(&header, Some(v)).is_some_and(|s| s.extension == v.extension))
+        .copied()
+}
+
+fn media_event(media: Media) -> MediaEvent {
+    Event {
+        timing: Timing::Instant(media.time_modified),
+        id: media.path.clone(),
+        plugin: Plugin::get_type(),
+        event: media,
+    }
+}
+
+/// The encoded path of the directory of a file, see [`paths::encode_path`].
+fn parent_directory(path: &str) -> String {
+    path.rsplit_once('/')
+        .map(|v| v.0.to_string())
+        .unwrap_or_default()
+}
+
+/// Creates the indexes of the fields events are looked up by while scanning, limited to events of
+/// this plugin like [`geo::create_index`]. Paths are looked up by `id`, which is indexed already.
+async fn create_indexes(database: &Database) -> Result<(), Error> {
+    let index = |field: &str, name: &str| {
+        IndexModel::builder()
+            .keys(doc! { field: 1 })
+            .options(
+                IndexOptions::builder()
+                    .name(name.to_string())
+                    .partial_filter_expression(Database::generate_find_plugin_filter(
+                        AvailablePlugins::timeline_plugin_media_scan,
+                    ))
+                    .build(),
+            )
+            .build()
+    };
+    database
+        .get_events::<Document>()
+        .create_indexes(
+            [
+                index(
+                    "event.sample_hash",
+                    "timeline_plugin_media_scan_sample_hash",
+                ),
+                index(
+                    "event.location_name",
+                    "timeline_plugin_media_scan_location_name",
+                ),
+            ],
+            None,
+        )
+        .await
+        .map(|_| ())
+}
 
-        next_result = dir.next_entry().await;
+/// Matches the events of the files directly in `directory`. Their ids are their paths, so the range
+/// keeps the query on the index of `id`, the regular expression leaves out subdirectories.
+fn directory_filter(directory: &str) -> Document {
+    doc! {
+        "id": {
+            "$gte": format!("{}/", directory),
+            // The character after `/`.
+            "$lt": format!("{}0", directory),
+            "$regex": format!("^{}/[^/]+$", escape_regex(directory))
+        }
     }
+}
 
-    Ok((found_media, updated_newest))
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The capture time, position and technical details of a file, as far as its format is
+/// understood.
+async fn read_metadata(path: PathBuf, media_type: &'static MediaType) -> FileMetadata {
+    task::spawn_blocking(move || match media_type.metadata {
+        MetadataFormat::Exif => {
+            let exif = image_metadata::read_exif(&path);
+            FileMetadata {
+                creation_time: exif.as_ref().and_then(image_metadata::read_capture_time),
+                details: image_metadata::read_details(&path, exif.as_ref()),
+                position: exif.as_ref().and_then(image_metadata::read_position),
+                identifiers: exif
+                    .as_ref()
+                    .map(image_metadata::read_identifiers)
+                    .unwrap_or_default(),
+                ..Default::default()
+            }
+        }
+        MetadataFormat::Unsupported if media_type.kind == MediaKind::Image => FileMetadata {
+            details: image_metadata::read_details(&path, None),
+            ..Default::default()
+        },
+        MetadataFormat::Unsupported => FileMetadata::default(),
+        format => container_metadata::read_container_metadata(&path, format).unwrap_or_default(),
+    })
+    .await
+    .unwrap_or_default()
 }
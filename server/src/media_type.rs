@@ -0,0 +1,245 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::path::Path,
+    MediaKind::{Audio, Image, Video},
+    MetadataFormat::{Adts, Exif, Flac, Id3, IsoBmff, Matroska, Ogg, Riff, Unsupported},
+};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+}
+
+/// Where the embedded capture time of a format is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataFormat {
+    Exif,
+    IsoBmff,
+    Matroska,
+    Id3,
+    Adts,
+    Ogg,
+    Flac,
+    Riff,
+    Unsupported,
+}
+
+#[derive(Debug)]
+pub struct MediaType {
+    pub extension: &'static str,
+    pub kind: MediaKind,
+    pub mime_type: &'static str,
+    pub metadata: MetadataFormat,
+}
+
+const fn media_type(
+    extension: &'static str,
+    kind: MediaKind,
+    mime_type: &'static str,
+    metadata: MetadataFormat,
+) -> MediaType {
+    MediaType {
+        extension,
+        kind,
+        mime_type,
+        metadata,
+    }
+}
+
+pub static MEDIA_TYPES: [MediaType; 30] = [
+    media_type("jpg", Image, "image/jpeg", Exif),
+    media_type("jpeg", Image, "image/jpeg", Exif),
+    media_type("png", Image, "image/png", Exif),
+    media_type("gif", Image, "image/gif", Unsupported),
+    media_type("webp", Image, "image/webp", Exif),
+    media_type("avif", Image, "image/avif", Exif),
+    media_type("heic", Image, "image/heic", Exif),
+    media_type("heif", Image, "image/heif", Exif),
+    media_type("bmp", Image, "image/bmp", Unsupported),
+    media_type("tif", Image, "image/tiff", Exif),
+    media_type("tiff", Image, "image/tiff", Exif),
+    media_type("dng", Image, "image/x-adobe-dng", Exif),
+    media_type("cr2", Image, "image/x-canon-cr2", Exif),
+    media_type("nef", Image, "image/x-nikon-nef", Exif),
+    media_type("arw", Image, "image/x-sony-arw", Exif),
+    media_type("mp4", Video, "video/mp4", IsoBmff),
+    media_type("m4v", Video, "video/x-m4v", IsoBmff),
+    media_type("mov", Video, "video/quicktime", IsoBmff),
+    media_type("3gp", Video, "video/3gpp", IsoBmff),
+    media_type("mkv", Video, "video/x-matroska", Matroska),
+    media_type("webm", Video, "video/webm", Matroska),
+    media_type("avi", Video, "video/x-msvideo", Unsupported),
+    media_type("mp3", Audio, "audio/mpeg", Id3),
+    media_type("m4a", Audio, "audio/mp4", IsoBmff),
+    media_type("aac", Audio, "audio/aac", Adts),
+    media_type("opus", Audio, "audio/opus", Ogg),
+    media_type("ogg", Audio, "audio/ogg", Ogg),
+    media_type("oga", Audio, "audio/ogg", Ogg),
+    media_type("flac", Audio, "audio/flac", Flac),
+    media_type("wav", Audio, "audio/wav", Riff),
+];
+
+/// Extensions that are indexed if neither the plugin nor the location configures its own set.
+pub const DEFAULT_EXTENSIONS: [&str; 12] = [
+    "png", "jpg", "mp4", "mkv", "webm", "jpeg", "mov", "heic", "gif", "mp3", "opus", "m4a",
+];
+
+pub fn by_extension(extension: &str) -> Option<&'static MediaType> {
+    let extension = extension.to_lowercase();
+    MEDIA_TYPES.iter().find(|v| v.extension == extension)
+}
+
+pub fn by_path(path: &Path) -> Option<&'static MediaType> {
+    by_extension(path.extension()?.to_str()?)
+}
+
+/// Number of bytes at the start of a file that [`sniff`] looks at.
+pub const SNIFF_LENGTH: usize = 64;
+
+/// Detects the media type of a file from its leading bytes. Some signatures are shared by a whole
+/// family of formats (TIFF based raw files, generic ISO BMFF brands), in that case the type of the
+/// file extension is kept if it belongs to that family.
+pub fn sniff(header: &[u8], hint: Option<&'static MediaType>) -> Option<&'static MediaType> {
+    let (extension, family): (&str, &[&str]) = match header {
+        [0xFF, 0xD8, 0xFF, ..] => ("jpg", &[]),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => ("png", &[]),
+        [b'G', b'I', b'F', b'8', ..] => ("gif", &[]),
+        [b'B', b'M', ..] => ("bmp", &[]),
+        [b'I', b'I', 0x2A, 0x00, _, _, _, _, b'C', b'R', ..] => ("cr2", &[]),
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => {
+            ("tiff", &["tif", "dng", "nef", "arw", "cr2"])
+        }
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => ("webp", &[]),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => ("wav", &[]),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => ("avi", &[]),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => sniff_ftyp(header)?,
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => match contains(header, b"webm") {
+            true => ("webm", &[]),
+            false => ("mkv", &[]),
+        },
+        [b'f', b'L', b'a', b'C', ..] => ("flac", &[]),
+        [b'O', b'g', b'g', b'S', ..] => match contains(header, b"OpusHead") {
+            true => ("opus", &[]),
+            false => ("ogg", &[]),
+        },
+        [b'I', b'D', b'3', ..] => ("mp3", &[]),
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => ("aac", &[]),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => ("mp3", &[]),
+        _ => return None,
+    };
+    let sniffed = by_extension(extension)?;
+    match hint {
+        Some(hint) if family.contains(&hint.extension) || hint.mime_type == sniffed.mime_type => {
+            Some(hint)
+        }
+        _ => Some(sniffed),
+    }
+}
+
+/// ISO BMFF files are told apart by the major and compatible brands of their `ftyp` box.
+fn sniff_ftyp(header: &[u8]) -> Option<(&'static str, &'static [&'static str])> {
+    if header.len() < 12 {
+        return None;
+    }
+    let size = u32::from_be_bytes(header.get(0..4)?.try_into().ok()?) as usize;
+    let brands = header
+        .get(8..size.min(header.len()))?
+        .chunks_exact(4)
+        .enumerate()
+        // The minor version follows the major brand.
+        .filter(|(i, _)| *i != 1)
+        .map(|(_, v)| v)
+        .collect::<Vec<_>>();
+    let has = |list: &[&[u8; 4]]| brands.iter().any(|v| list.iter().any(|b| v == b));
+    Some(match brands.first().copied()? {
+        b"avif" | b"avis" => ("avif", &[]),
+        b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" => ("heic", &["heif"]),
+        b"mif1" | b"msf1" if has(&[b"avif", b"avis"]) => ("avif", &[]),
+        b"mif1" | b"msf1" => ("heif", &["heic"]),
+        b"qt  " => ("mov", &[]),
+        b"M4A " | b"M4B " => ("m4a", &[]),
+        b"M4V " | b"M4VH" | b"M4VP" => ("m4v", &[]),
+        v if v.starts_with(b"3g") => ("3gp", &[]),
+        _ => ("mp4", &["m4v", "m4a", "mov", "3gp"]),
+    })
+}
+
+fn contains(header: &[u8], needle: &[u8]) -> bool {
+    header.windows(needle.len()).any(|v| v == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brands: &[&[u8; 4]]) -> Vec<u8> {
+        let mut header = ((8 + brands.len() * 4) as u32).to_be_bytes().to_vec();
+        header.extend_from_slice(b"ftyp");
+        for brand in brands {
+            header.extend_from_slice(*brand);
+        }
+        header
+    }
+
+    fn sniffed(header: &[u8], hint: Option<&str>) -> Option<&'static str> {
+        sniff(header, hint.and_then(by_extension)).map(|v| v.extension)
+    }
+
+    #[test]
+    fn detects_signatures() {
+        assert_eq!(sniffed(&[0xFF, 0xD8, 0xFF, 0xE1], Some("png")), Some("jpg"));
+        assert_eq!(sniffed(b"\x89PNG\r\n\x1A\n", None), Some("png"));
+        assert_eq!(sniffed(b"RIFF\0\0\0\0WEBPVP8 ", None), Some("webp"));
+        assert_eq!(sniffed(b"OggS\0\x02\0\0OpusHead", None), Some("opus"));
+        assert_eq!(sniffed(b"ID3\x04\0", None), Some("mp3"));
+        assert_eq!(sniffed(b"plain text", Some("jpg")), None);
+    }
+
+    #[test]
+    fn keeps_the_extension_within_a_family() {
+        assert_eq!(sniffed(b"II\x2A\0\x08\0\0\0", Some("dng")), Some("dng"));
+        assert_eq!(sniffed(b"II\x2A\0\x08\0\0\0", None), Some("tiff"));
+        assert_eq!(sniffed(&[0xFF, 0xD8, 0xFF], Some("jpeg")), Some("jpeg"));
+        assert_eq!(
+            sniffed(&ftyp(&[b"isom", b"\0\0\0\0"]), Some("mov")),
+            Some("mov")
+        );
+        assert_eq!(
+            sniffed(&ftyp(&[b"mif1", b"\0\0\0\0"]), Some("heic")),
+            Some("heic")
+        );
+    }
+
+    #[test]
+    fn reads_ftyp_brands() {
+        assert_eq!(
+            sniffed(&ftyp(&[b"heic", b"\0\0\0\0"]), Some("jpg")),
+            Some("heic")
+        );
+        assert_eq!(
+            sniffed(&ftyp(&[b"qt  ", b"\0\0\0\0"]), Some("mp4")),
+            Some("mov")
+        );
+        assert_eq!(
+            sniffed(&ftyp(&[b"M4V ", b"\0\0\0\0"]), Some("mp4")),
+            Some("m4v")
+        );
+        assert_eq!(
+            sniffed(&ftyp(&[b"mif1", b"\0\0\0\0", b"mif1", b"avif"]), None),
+            Some("avif")
+        );
+        // The minor version is not a brand.
+        assert_eq!(sniffed(&ftyp(&[b"mif1", b"avif"]), None), Some("heif"));
+    }
+
+    #[test]
+    fn truncated_ftyp_is_unknown() {
+        assert_eq!(sniffed(b"\0\0\0\x18ftyp", Some("mp4")), None);
+        assert_eq!(sniffed(b"\0\0\0\x18ftyphe", None), None);
+        assert_eq!(sniffed(b"\0\0\0\x04ftypheic", None), None);
+        assert_eq!(sniffed(b"\0\0\0\x18ftypheic", None), Some("heic"));
+    }
+}
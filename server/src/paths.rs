@@ -0,0 +1,107 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// Encodes a path as a string without losing anything of paths that are not valid UTF-8. Valid
+/// UTF-8 is kept as it is, except for `%` which becomes `%25`. Every other byte becomes `%XX`.
+/// Prefixes are kept, so the encoding of a file starts with the encoding of its directory.
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for chunk in path_bytes(path).utf8_chunks() {
+        for character in chunk.valid().chars() {
+            match character {
+                '%' => encoded.push_str("%25"),
+                v => encoded.push(v),
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Reverses [`encode_path`].
+pub fn decode_path(encoded: &str) -> PathBuf {
+    let encoded = encoded.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = match encoded[i] {
+            b'%' => encoded
+                .get(i + 1..i + 3)
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .and_then(|v| u8::from_str_radix(v, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(v) => {
+                bytes.push(v);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> &[u8] {
+    std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(<OsString as std::os::unix::ffi::OsStringExt>::from_vec(
+        bytes,
+    ))
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_encoded_bytes()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(OsString::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_utf8_and_escapes_percent() {
+        let path = Path::new("/media/100% fun/Ürlaub 🌊.jpg");
+        assert_eq!(encode_path(path), "/media/100%25 fun/Ürlaub 🌊.jpg");
+        assert_eq!(decode_path(&encode_path(path)), path);
+    }
+
+    #[test]
+    fn percent_sequences_round_trip() {
+        for name in ["%41", "%", "%%", "a%2", "%zz", "%25"] {
+            let path = PathBuf::from(format!("/media/{}", name));
+            assert_eq!(decode_path(&encode_path(&path)), path, "{}", name);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_round_trip() {
+        let path = path_from_bytes(b"/media/caf\xE9 %41/\xFF\xFE.jpg".to_vec());
+        let encoded = encode_path(&path);
+        assert_eq!(encoded, "/media/caf%E9 %2541/%FF%FE.jpg");
+        assert_eq!(decode_path(&encoded), path);
+    }
+
+    #[test]
+    fn directories_stay_prefixes() {
+        let directory = encode_path(Path::new("/media/50%"));
+        assert!(encode_path(Path::new("/media/50%/a.jpg")).starts_with(&format!("{}/", directory)));
+    }
+}
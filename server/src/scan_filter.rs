@@ -0,0 +1,242 @@
+use {
+    crate::{
+        media_type::{self, MediaType},
+        sidecar::SidecarMode,
+    },
+    globset::{GlobBuilder, GlobSet, GlobSetBuilder},
+    ignore::{
+        gitignore::{Gitignore, GitignoreBuilder},
+        Match,
+    },
+    server_api::external::tokio::task,
+    std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+pub const IGNORE_FILE: &str = ".mediaignore";
+
+/// Include and exclude globs of a media location. Patterns are matched against the path relative
+/// to the location, patterns without a `/` match a file or directory name at any depth. A pattern
+/// matching a directory matches everything below it as well. Also knows
+/// whether sidecars next to the media of the location are read.
+#[derive(Default)]
+pub struct ScanFilter {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    media_types: Vec<&'static MediaType>,
+    sidecar_mode: SidecarMode,
+}
+
+impl ScanFilter {
+    pub fn new(
+        root: &Path,
+        include: &[String],
+        exclude: &[String],
+        media_types: Vec<&'static MediaType>,
+        sidecar_mode: SidecarMode,
+    ) -> Result<Self, String> {
+        Ok(ScanFilter {
+            media_types,
+            sidecar_mode,
+            root: root.to_path_buf(),
+            include: match include.is_empty() {
+                true => None,
+                false => Some(build_glob_set(include)?),
+            },
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    /// Whether a file might be media of this location. Files without an extension are candidates
+    /// as well, their type is only known once their content is sniffed.
+    pub fn is_candidate(&self, path: &Path) -> bool {
+        match path.extension() {
+            None => true,
+            Some(_) => media_type::by_path(path).is_some_and(|v| self.allows(v)),
+        }
+    }
+
+    pub fn media_types(&self) -> &[&'static MediaType] {
+        &self.media_types
+    }
+
+    pub fn sidecar_mode(&self) -> SidecarMode {
+        self.sidecar_mode
+    }
+
+    /// Whether media of this type is indexed for this location.
+    pub fn allows(&self, media_type: &MediaType) -> bool {
+        self.media_types
+            .iter()
+            .any(|v| v.extension == media_type.extension)
+    }
+
+    /// Whether an entry found while walking the location should be skipped. Directories that are
+    /// skipped are not descended into.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool, ignores: &[Arc<Gitignore>]) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.exclude.is_match(relative) {
+            return true;
+        }
+        for ignore in ignores.iter().rev() {
+            match ignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => break,
+                Match::None => {}
+            }
+        }
+        match (&self.include, is_dir) {
+            (Some(include), false) => !include.is_match(relative),
+            _ => false,
+        }
+    }
+
+    /// Like [`ScanFilter::is_excluded`], but for a single path outside of a walk, so every parent
+    /// directory up to the location root is checked as well.
+    pub async fn is_path_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        let mut ignores = read_ignore_file(&self.root)
+            .await
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut current = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let current_is_dir = components.peek().is_some() || is_dir;
+            if self.is_excluded(&current, current_is_dir, &ignores) {
+                return true;
+            }
+            if current_is_dir {
+                ignores.extend(read_ignore_file(&current).await);
+            }
+        }
+        false
+    }
+
+    /// The `.mediaignore` files that apply to entries of `directory`.
+    pub async fn ignores_for(&self, directory: &Path) -> Vec<Arc<Gitignore>> {
+        let mut ignores = Vec::new();
+        let mut current = self.root.clone();
+        ignores.extend(read_ignore_file(&current).await);
+        if let Ok(relative) = directory.strip_prefix(&self.root) {
+            for component in relative.components() {
+                current.push(component);
+                ignores.extend(read_ignore_file(&current).await);
+            }
+        }
+        ignores
+    }
+}
+
+/// [`load_ignore_file`] on a blocking thread, for checks outside of a walk.
+async fn read_ignore_file(directory: &Path) -> Option<Arc<Gitignore>> {
+    let directory = directory.to_path_buf();
+    task::spawn_blocking(move || load_ignore_file(&directory))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Loads the `.mediaignore` file of a directory, if it has one.
+pub fn load_ignore_file(directory: &Path) -> Option<Arc<Gitignore>> {
+    let path = directory.join(IGNORE_FILE);
+    if !path.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(directory);
+    if let Some(e) = builder.add(&path) {
+        eprintln!(
+            "The Media Scan plugin was unable to fully read {:?}: {}",
+            path, e
+        );
+    }
+    match builder.build() {
+        Ok(v) => Some(Arc::new(v)),
+        Err(e) => {
+            eprintln!("The Media Scan plugin was unable to read {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.trim_end_matches('/');
+        let pattern = match pattern.contains('/') {
+            true => pattern.trim_start_matches('/').to_string(),
+            false => format!("**/{}", pattern),
+        };
+        // The entry itself and, if it is a directory, everything below it.
+        for pattern in [pattern.clone(), format!("{}/**", pattern)] {
+            builder.add(
+                GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("Invalid glob pattern {}: {}", pattern, e))?,
+            );
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Unable to build glob patterns: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> ScanFilter {
+        let strings = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        ScanFilter::new(
+            Path::new("/photos"),
+            &strings(include),
+            &strings(exclude),
+            Vec::new(),
+            SidecarMode::default(),
+        )
+        .unwrap()
+    }
+
+    fn excluded(filter: &ScanFilter, path: &str) -> bool {
+        filter.is_excluded(&Path::new("/photos").join(path), false, &[])
+    }
+
+    #[test]
+    fn includes_directories() {
+        for pattern in ["DCIM", "DCIM/", "/DCIM"] {
+            let filter = filter(&[pattern], &[]);
+            assert!(!filter.is_excluded(Path::new("/photos/DCIM"), true, &[]));
+            assert!(!excluded(&filter, "DCIM/a.jpg"));
+            assert!(!excluded(&filter, "DCIM/100APPLE/a.jpg"));
+            assert!(excluded(&filter, "Downloads/a.jpg"));
+            assert!(excluded(&filter, "a.jpg"));
+        }
+        let filter = filter(&["DCIM"], &[]);
+        assert!(!excluded(&filter, "Phone/DCIM/a.jpg"));
+        assert!(excluded(&filter, "DCIM2/a.jpg"));
+    }
+
+    #[test]
+    fn includes_file_patterns() {
+        let filter = filter(&["*.jpg"], &[]);
+        assert!(!excluded(&filter, "a.jpg"));
+        assert!(!excluded(&filter, "DCIM/a.jpg"));
+        assert!(excluded(&filter, "DCIM/a.png"));
+    }
+
+    #[test]
+    fn excludes_directory_contents() {
+        let filter = filter(&[], &["Screenshots", "DCIM/.thumbnails"]);
+        assert!(excluded(&filter, "Screenshots/a.png"));
+        assert!(excluded(&filter, "DCIM/.thumbnails/a.jpg"));
+        assert!(!excluded(&filter, "DCIM/a.jpg"));
+        assert!(!excluded(&filter, "Other/.thumbnails/a.jpg"));
+    }
+}
@@ -0,0 +1,383 @@
+use {
+    crate::{container_metadata, geo::GpsPosition, CaptureTime, FileMetadata, TimeSource},
+    serde::{Deserialize, Serialize},
+    server_api::external::types::external::{
+        chrono::{DateTime, Utc},
+        serde_json,
+    },
+    std::{
+        collections::HashMap,
+        ffi::{OsStr, OsString},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Google Takeout shortens the names of its sidecars to this many characters before `.json`.
+const TAKEOUT_NAME_LENGTH: usize = 46;
+const TAKEOUT_SUFFIX: &str = ".supplemental-metadata";
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+const PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+
+/// How the metadata of sidecar files next to media is used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarMode {
+    /// Sidecars win over what the file itself says.
+    #[default]
+    Override,
+    /// Sidecars only add what the file does not say.
+    Fill,
+    /// Sidecars are not read.
+    Ignore,
+}
+
+/// What a sidecar says about its media file.
+#[derive(Debug, Default)]
+pub struct Sidecar {
+    pub capture_time: Option<CaptureTime>,
+    pub position: Option<GpsPosition>,
+    pub description: Option<String>,
+}
+
+impl Sidecar {
+    /// Merges the sidecar into the metadata read from the file itself.
+    pub fn apply(self, metadata: &mut FileMetadata, mode: SidecarMode) {
+        fn merge<T>(target: &mut Option<T>, value: Option<T>, mode: SidecarMode) {
+            match mode {
+                SidecarMode::Override if value.is_some() => *target = value,
+                SidecarMode::Fill if target.is_none() => *target = value,
+                _ => {}
+            }
+        }
+        merge(&mut metadata.creation_time, self.capture_time, mode);
+        merge(&mut metadata.position, self.position, mode);
+        merge(&mut metadata.description, self.description, mode);
+    }
+
+    fn or(self, other: Sidecar) -> Sidecar {
+        Sidecar {
+            capture_time: self.capture_time.or(other.capture_time),
+            position: self.position.or(other.position),
+            description: self.description.or(other.description),
+        }
+    }
+}
+
+/// Whether a file might be a sidecar, judged by its extension.
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|v| v.to_str())
+        .is_some_and(|v| v.eq_ignore_ascii_case("json") || v.eq_ignore_ascii_case("xmp"))
+}
+
+/// The names the sidecars of a media file can have, in the order their values are preferred: XMP
+/// as written by darktable (`IMG_1.jpg.xmp`) and Lightroom (`IMG_1.xmp`), then Google Takeout
+/// JSON. Takeout moves the counter of duplicate names behind the extension (`IMG_1(2).jpg` is
+/// described by `IMG_1.jpg(2).json`) and shortens long names.
+pub fn sidecar_names(file_name: &OsStr) -> Vec<OsString> {
+    let Some(name) = file_name.to_str() else {
+        return Vec::new();
+    };
+    let mut names = vec![format!("{}.xmp", name)];
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name, None),
+    };
+    if extension.is_some() {
+        names.push(format!("{}.xmp", stem));
+    }
+    let (base, counter) = match (stem.strip_suffix(')'), extension) {
+        (Some(v), Some(extension)) => match v.rsplit_once('(') {
+            Some((base, counter)) if counter.bytes().all(|v| v.is_ascii_digit()) => {
+                (format!("{}.{}", base, extension), format!("({})", counter))
+            }
+            _ => (name.to_string(), String::new()),
+        },
+        _ => (name.to_string(), String::new()),
+    };
+    for takeout in [format!("{}{}", base, TAKEOUT_SUFFIX), base] {
+        let shortened: String = takeout.chars().take(TAKEOUT_NAME_LENGTH).collect();
+        names.push(format!("{}{}.json", shortened, counter));
+    }
+    names.dedup();
+    names.into_iter().map(OsString::from).collect()
+}
+
+/// The latest modification time of the sidecars of a file that are in a directory listing. It is
+/// part of the fingerprint of the file, so an edited sidecar causes it to be read again.
+pub fn latest_modified(
+    file_name: &OsStr,
+    sidecars: &HashMap<OsString, DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    sidecar_names(file_name)
+        .iter()
+        .filter_map(|v| sidecars.get(v))
+        .max()
+        .copied()
+}
+
+/// Like [`latest_modified`], but for a single file outside of a directory walk.
+pub fn find_latest_modified(path: &Path) -> Option<DateTime<Utc>> {
+    let directory = path.parent()?;
+    sidecar_names(path.file_name()?)
+        .iter()
+        .filter_map(|v| std::fs::metadata(directory.join(v)).ok()?.modified().ok())
+        .max()
+        .map(DateTime::from)
+}
+
+/// The media files in the directory of a sidecar that it might describe.
+pub fn find_media(sidecar: &Path) -> Vec<PathBuf> {
+    let (Some(directory), Some(name)) = (sidecar.parent(), sidecar.file_name()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|v| v.ok())
+        .filter(|v| sidecar_names(&v.file_name()).iter().any(|v| v == name))
+        .map(|v| v.path())
+        .collect()
+}
+
+/// Reads all sidecars of a media file and combines them, preferring the values of the sidecars
+/// that come first in [`sidecar_names`].
+pub fn read_sidecars(path: &Path) -> Option<Sidecar> {
+    let directory = path.parent()?;
+    sidecar_names(path.file_name()?)
+        .into_iter()
+        .map(|v| directory.join(v))
+        .filter_map(|v| read_sidecar(&v))
+        .reduce(Sidecar::or)
+}
+
+fn read_sidecar(path: &Path) -> Option<Sidecar> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "json" => parse_takeout(&text),
+        "xmp" => parse_xmp(&text),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TakeoutMetadata {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    photo_taken_time: Option<TakeoutTime>,
+    #[serde(default)]
+    geo_data: Option<TakeoutPosition>,
+    #[serde(default)]
+    geo_data_exif: Option<TakeoutPosition>,
+}
+
+#[derive(Deserialize)]
+struct TakeoutTime {
+    /// Unix seconds, as a string.
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct TakeoutPosition {
+    latitude: f64,
+    longitude: f64,
+    #[serde(default)]
+    altitude: Option<f64>,
+}
+
+/// Parses the JSON Google Takeout writes next to every photo and video. Unknown positions are
+/// written as `0.0, 0.0`.
+fn parse_takeout(text: &str) -> Option<Sidecar> {
+    let metadata: TakeoutMetadata = serde_json::from_str(text).ok()?;
+    let position = [metadata.geo_data, metadata.geo_data_exif]
+        .into_iter()
+        .flatten()
+        .filter(|v| v.latitude != 0.0 || v.longitude != 0.0)
+        .find_map(|v| GpsPosition::new(v.latitude, v.longitude, v.altitude));
+    Some(Sidecar {
+        capture_time: metadata
+            .photo_taken_time
+            .and_then(|v| v.timestamp.parse().ok())
+            .and_then(|v| DateTime::from_timestamp(v, 0))
+            .map(|time| CaptureTime {
+                time,
+                source: TimeSource::Sidecar,
+            }),
+        position,
+        description: metadata.description.filter(|v| !v.trim().is_empty()),
+    })
+}
+
+/// Parses an XMP sidecar as written by Lightroom, darktable and digiKam.
+fn parse_xmp(text: &str) -> Option<Sidecar> {
+    let document = roxmltree::Document::parse(text).ok()?;
+    let property = |namespace: &str, name: &str| xmp_property(&document, namespace, name);
+    let capture_time = [
+        (EXIF, "DateTimeOriginal"),
+        (PHOTOSHOP, "DateCreated"),
+        (XMP, "CreateDate"),
+    ]
+    .into_iter()
+    .find_map(|(namespace, name)| container_metadata::parse_text_time(&property(namespace, name)?))
+    .map(|v| CaptureTime {
+        source: match v.source {
+            TimeSource::ContainerLocalTime => TimeSource::SidecarLocalTime,
+            _ => TimeSource::Sidecar,
+        },
+        ..v
+    });
+    // Reference 1 means below sea level.
+    let altitude = property(EXIF, "GPSAltitude")
+        .and_then(|v| parse_rational(&v))
+        .map(|v| match property(EXIF, "GPSAltitudeRef").as_deref() {
+            Some("1") => -v,
+            _ => v,
+        });
+    let position = property(EXIF, "GPSLatitude")
+        .zip(property(EXIF, "GPSLongitude"))
+        .and_then(|(latitude, longitude)| {
+            GpsPosition::new(
+                parse_xmp_coordinate(&latitude)?,
+                parse_xmp_coordinate(&longitude)?,
+                altitude,
+            )
+        });
+    Some(Sidecar {
+        capture_time,
+        position,
+        description: property(DC, "description"),
+    })
+}
+
+/// A simple property, written either as an attribute of a description or as an element. Of
+/// language alternatives and lists the first item is used.
+fn xmp_property(document: &roxmltree::Document, namespace: &str, name: &str) -> Option<String> {
+    let value = document.descendants().find_map(|node| {
+        if let Some(v) = node.attribute((namespace, name)) {
+            return Some(v);
+        }
+        let tag = node.tag_name();
+        if tag.namespace() != Some(namespace) || tag.name() != name {
+            return None;
+        }
+        node.descendants()
+            .find(|v| v.tag_name().namespace() == Some(RDF) && v.tag_name().name() == "li")
+            .unwrap_or(node)
+            .text()
+    })?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Parses an XMP GPS coordinate, `DDD,MM,SSk` or `DDD,MM.mmk` with `k` being one of `NSEW`.
+fn parse_xmp_coordinate(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let sign = match text.chars().last()? {
+        'N' | 'E' => 1.0,
+        'S' | 'W' => -1.0,
+        _ => return None,
+    };
+    let parts: Vec<f64> = text[..text.len() - 1]
+        .split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    Some(
+        sign * parts
+            .iter()
+            .zip([1.0, 60.0, 3600.0])
+            .map(|(v, unit)| v / unit)
+            .sum::<f64>(),
+    )
+}
+
+fn parse_rational(text: &str) -> Option<f64> {
+    let value = match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            numerator.trim().parse::<f64>().ok()? / denominator.trim().parse::<f64>().ok()?
+        }
+        None => text.trim().parse().ok()?,
+    };
+    value.is_finite().then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(file_name: &str) -> Vec<String> {
+        sidecar_names(OsStr::new(file_name))
+            .into_iter()
+            .map(|v| v.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn names_xmp_and_takeout_sidecars() {
+        assert_eq!(
+            names("IMG_1.jpg"),
+            [
+                "IMG_1.jpg.xmp",
+                "IMG_1.xmp",
+                "IMG_1.jpg.supplemental-metadata.json",
+                "IMG_1.jpg.json",
+            ]
+        );
+        assert_eq!(
+            names("README"),
+            [
+                "README.xmp",
+                "README.supplemental-metadata.json",
+                "README.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_the_takeout_counter_behind_the_extension() {
+        assert_eq!(
+            names("IMG_1(2).jpg"),
+            [
+                "IMG_1(2).jpg.xmp",
+                "IMG_1(2).xmp",
+                "IMG_1.jpg.supplemental-metadata(2).json",
+                "IMG_1.jpg(2).json",
+            ]
+        );
+        // Only numbers are counters.
+        assert_eq!(names("IMG(a).jpg")[3], "IMG(a).jpg.json");
+    }
+
+    #[test]
+    fn shortens_long_takeout_names() {
+        // Both Takeout names shorten to the same one.
+        let name = format!("{}.jpg", "a".repeat(50));
+        assert_eq!(names(&name)[2..], [format!("{}.json", "a".repeat(46))]);
+        // The supplemental metadata suffix is cut first.
+        assert_eq!(
+            names("PXL_20230101_123456789.jpg")[2..],
+            [
+                "PXL_20230101_123456789.jpg.supplemental-metada.json",
+                "PXL_20230101_123456789.jpg.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn shortens_before_adding_the_counter() {
+        let name = format!("{}(3).jpg", "b".repeat(50));
+        assert_eq!(names(&name)[2..], [format!("{}(3).json", "b".repeat(46))]);
+        // Characters, not bytes, are counted.
+        let name = format!("{}.jpg", "é".repeat(50));
+        assert_eq!(names(&name)[2..], [format!("{}.json", "é".repeat(46))]);
+    }
+}
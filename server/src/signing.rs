@@ -0,0 +1,344 @@
+use {
+    base64::Engine,
+    hmac::{Hmac, Mac},
+    rsa::{
+        pkcs1v15::{Signature, SigningKey, VerifyingKey},
+        sha2::{Digest, Sha256},
+        signature::{Keypair, RandomizedSigner, SignatureEncoding, Verifier},
+        traits::{PrivateKeyParts, PublicKeyParts},
+        RsaPrivateKey,
+    },
+    serde::{Deserialize, Serialize},
+    server_api::external::{
+        rocket::http::Status,
+        types::external::chrono::{DateTime, Duration, Utc},
+    },
+};
+
+/// Marks tokens signed with [`SignatureScheme::Hmac`].
+const HMAC_MARKER: &str = "h";
+/// Marks tokens signed with [`SignatureScheme::Rsa`].
+const RSA_MARKER: &str = "r";
+
+/// What a signed URL may be used for, so a signature for one route can not be used on another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignaturePurpose {
+    Full,
+    Thumbnail,
+    Poster,
+    Preview,
+}
+
+impl SignaturePurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SignaturePurpose::Full => "full",
+            SignaturePurpose::Thumbnail => "thumb",
+            SignaturePurpose::Poster => "poster",
+            SignaturePurpose::Preview => "preview",
+        }
+    }
+}
+
+/// How new signatures are created. Both schemes are always accepted when verifying, so switching
+/// does not invalidate URLs that were already handed out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureScheme {
+    /// Randomized RSA PKCS#1 v1.5 signatures, slow to create.
+    Rsa,
+    /// HMAC-SHA256 with a key derived from the RSA key, cheap and deterministic.
+    #[default]
+    Hmac,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    Invalid,
+    Expired,
+}
+
+impl From<SignatureError> for Status {
+    fn from(value: SignatureError) -> Self {
+        match value {
+            SignatureError::Invalid => Status::Unauthorized,
+            // The URL was valid once, the client has to fetch a fresh one.
+            SignatureError::Expired => Status::Gone,
+        }
+    }
+}
+
+/// One configured key together with everything derived from it.
+struct Key {
+    id: String,
+    signing_key: SigningKey<Sha256>,
+    verifying_key: VerifyingKey<Sha256>,
+    hmac_key: [u8; 32],
+}
+
+impl Key {
+    fn new(key: &RsaPrivateKey) -> Self {
+        let signing_key = SigningKey::new(key.clone());
+        let mut id = Sha256::new();
+        id.update(key.n().to_bytes_be());
+        id.update(key.e().to_bytes_be());
+        let mut hmac_key = Sha256::new();
+        hmac_key.update(b"timeline_plugin_media_scan url signatures\n");
+        hmac_key.update(key.d().to_bytes_be());
+        Key {
+            id: base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(&id.finalize()[..6]),
+            verifying_key: signing_key.verifying_key(),
+            signing_key,
+            hmac_key: hmac_key.finalize().into(),
+        }
+    }
+
+    fn hmac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.hmac_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn verify(&self, scheme: SignatureScheme, payload: &str, signature: &str) -> bool {
+        match scheme {
+            SignatureScheme::Rsa => verify_string(&self.verifying_key, payload, signature),
+            SignatureScheme::Hmac => {
+                match base64::prelude::BASE64_URL_SAFE_NO_PAD.decode(signature) {
+                    Ok(v) => self.hmac(payload).verify_slice(&v).is_ok(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+/// Signs with the active key and verifies with the active and all previous keys, so URLs that
+/// were handed out stay valid while keys are rotated.
+pub struct Signer {
+    scheme: SignatureScheme,
+    active: Key,
+    previous: Vec<Key>,
+}
+
+impl Signer {
+    pub fn new(
+        active: &RsaPrivateKey,
+        previous: &[RsaPrivateKey],
+        scheme: SignatureScheme,
+    ) -> Self {
+        Signer {
+            scheme,
+            active: Key::new(active),
+            previous: previous.iter().map(Key::new).collect(),
+        }
+    }
+
+    /// Signs access to a path for a purpose until at least `lifetime` from now. The returned token
+    /// is `<expiry as unix seconds>.<scheme>.<key id>.<signature>`.
+    pub fn sign_media(&self, purpose: SignaturePurpose, path: &str, lifetime: Duration) -> String {
+        let expires = expiry(lifetime);
+        let payload = payload(purpose, expires, path);
+        let (marker, signature) = match self.scheme {
+            SignatureScheme::Rsa => (RSA_MARKER, sign_string(&self.active.signing_key, &payload)),
+            SignatureScheme::Hmac => (
+                HMAC_MARKER,
+                base64::prelude::BASE64_URL_SAFE_NO_PAD
+                    .encode(self.active.hmac(&payload).finalize().into_bytes()),
+            ),
+        };
+        format!("{}.{}.{}.{}", expires, marker, self.active.id, signature)
+    }
+
+    pub fn verify_media(
+        &self,
+        purpose: SignaturePurpose,
+        path: &str,
+        token: &str,
+    ) -> Result<(), SignatureError> {
+        let mut parts = token.split('.');
+        let expires = parts
+            .next()
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or(SignatureError::Invalid)?;
+        let payload = payload(purpose, expires, path);
+        let valid = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(marker), Some(id), Some(signature), None) => {
+                let scheme = scheme_of(marker).ok_or(SignatureError::Invalid)?;
+                std::iter::once(&self.active)
+                    .chain(self.previous.iter())
+                    .find(|v| v.id == id)
+                    .is_some_and(|v| v.verify(scheme, &payload, signature))
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(SignatureError::Invalid);
+        }
+        match DateTime::from_timestamp(expires, 0).is_some_and(|v| v > Utc::now()) {
+            true => Ok(()),
+            false => Err(SignatureError::Expired),
+        }
+    }
+}
+
+fn scheme_of(marker: &str) -> Option<SignatureScheme> {
+    match marker {
+        HMAC_MARKER => Some(SignatureScheme::Hmac),
+        RSA_MARKER => Some(SignatureScheme::Rsa),
+        _ => None,
+    }
+}
+
+/// The expiry of a signature created now. It is rounded up, so repeated queries produce the same
+/// URLs and the browser can reuse what it already downloaded.
+fn expiry(lifetime: Duration) -> i64 {
+    let granularity = (lifetime.num_seconds() / 4).clamp(1, 60 * 60);
+    let expires = (Utc::now() + lifetime).timestamp();
+    expires + (granularity - expires.rem_euclid(granularity)) % granularity
+}
+
+fn payload(purpose: SignaturePurpose, expires: i64, path: &str) -> String {
+    format!("{}\n{}\n{}", purpose.as_str(), expires, path)
+}
+
+fn sign_string(signing_key: &SigningKey<Sha256>, string: &str) -> String {
+    let mut rng = rand::thread_rng();
+    let signature = signing_key.sign_with_rng(&mut rng, string.as_bytes());
+    base64::prelude::BASE64_STANDARD.encode(signature.to_vec())
+}
+
+fn verify_string(verifying_key: &VerifyingKey<Sha256>, string: &str, signature: &str) -> bool {
+    let bytes = match base64::prelude::BASE64_STANDARD.decode(signature) {
+        Ok(v) => v,
+        Err(_e) => return false,
+    };
+    let bytes_slice: &[u8] = &bytes;
+    verifying_key
+        .verify(
+            string.as_bytes(),
+            &match Signature::try_from(bytes_slice) {
+                Ok(v) => v,
+                Err(_e) => return false,
+            },
+        )
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rand::thread_rng(), 512).expect("key generation works")
+    }
+
+    #[test]
+    fn accepts_own_signatures_of_both_schemes() {
+        let (key, other) = (key(), key());
+        for scheme in [SignatureScheme::Hmac, SignatureScheme::Rsa] {
+            let signer = Signer::new(&key, &[], scheme);
+            let token = signer.sign_media(SignaturePurpose::Full, "/a.jpg", Duration::hours(1));
+            assert_eq!(
+                signer.verify_media(SignaturePurpose::Full, "/a.jpg", &token),
+                Ok(())
+            );
+            assert_eq!(
+                signer.verify_media(SignaturePurpose::Full, "/b.jpg", &token),
+                Err(SignatureError::Invalid)
+            );
+            assert_eq!(
+                Signer::new(&other, &[], scheme).verify_media(
+                    SignaturePurpose::Full,
+                    "/a.jpg",
+                    &token
+                ),
+                Err(SignatureError::Invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn hmac_signatures_are_deterministic() {
+        let signer = Signer::new(&key(), &[], SignatureScheme::Hmac);
+        let sign = || signer.sign_media(SignaturePurpose::Full, "/a.jpg", Duration::hours(1));
+        assert_eq!(sign(), sign());
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let signer = Signer::new(&key(), &[], SignatureScheme::Hmac);
+        let token = signer.sign_media(SignaturePurpose::Full, "/a.jpg", Duration::minutes(-5));
+        assert_eq!(
+            signer.verify_media(SignaturePurpose::Full, "/a.jpg", &token),
+            Err(SignatureError::Expired)
+        );
+        // The expiry is part of what is signed.
+        let (_, rest) = token.split_once('.').unwrap();
+        let extended = format!("{}.{}", Utc::now().timestamp() + 3600, rest);
+        assert_eq!(
+            signer.verify_media(SignaturePurpose::Full, "/a.jpg", &extended),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn rejects_other_purposes() {
+        let signer = Signer::new(&key(), &[], SignatureScheme::Hmac);
+        let token = signer.sign_media(SignaturePurpose::Thumbnail, "/a.jpg", Duration::hours(1));
+        assert_eq!(
+            signer.verify_media(SignaturePurpose::Full, "/a.jpg", &token),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            signer.verify_media(SignaturePurpose::Thumbnail, "/a.jpg", &token),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let signer = Signer::new(&key(), &[], SignatureScheme::Hmac);
+        for token in [
+            "",
+            "abc",
+            "123",
+            "abc.def",
+            "123.!!",
+            "123.x.abc",
+            "123.h.!!",
+        ] {
+            assert_eq!(
+                signer.verify_media(SignaturePurpose::Full, "/a.jpg", token),
+                Err(SignatureError::Invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_rotated_keys_by_id() {
+        let (old, new, unrelated) = (key(), key(), key());
+        let token = Signer::new(&old, &[], SignatureScheme::Hmac).sign_media(
+            SignaturePurpose::Full,
+            "/a.jpg",
+            Duration::hours(1),
+        );
+        let rotated = Signer::new(&new, &[old], SignatureScheme::Hmac);
+        assert_eq!(
+            rotated.verify_media(SignaturePurpose::Full, "/a.jpg", &token),
+            Ok(())
+        );
+        // Tokens without a key id are not accepted, even if a key could verify them.
+        let parts: Vec<&str> = token.split('.').collect();
+        let without_id = format!("{}.{}.{}", parts[0], parts[1], parts[3]);
+        assert_eq!(
+            rotated.verify_media(SignaturePurpose::Full, "/a.jpg", &without_id),
+            Err(SignatureError::Invalid)
+        );
+        let forgotten = Signer::new(&new, &[unrelated], SignatureScheme::Hmac);
+        assert_eq!(
+            forgotten.verify_media(SignaturePurpose::Full, "/a.jpg", &token),
+            Err(SignatureError::Invalid)
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use {
+    crate::thumbnail,
+    server_api::external::tokio::task,
+    std::{collections::HashMap, io, path::Path},
+};
+
+/// Hashes up to this far apart are considered the same picture if nothing is configured.
+pub const DEFAULT_DISTANCE: u32 = 8;
+
+/// The difference hash of an image: one bit for each pair of neighboring pixels of a 9x8 grayscale
+/// version, set if the left one is brighter. Resized and recompressed copies get the same or a
+/// close hash.
+pub async fn perceptual_hash(path: &Path) -> Result<u64, io::Error> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || difference_hash(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn difference_hash(path: &Path) -> Result<u64, io::Error> {
+    let small = thumbnail::open_image(path)?
+        .thumbnail_exact(9, 8)
+        .to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    Ok(hash)
+}
+
+pub fn encode_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn decode_hash(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
+
+/// Groups hashes that are at most `max_distance` bits apart from the first hash of their group,
+/// so a chain of slightly different pictures does not become one group. Every hash joins the
+/// earliest group it fits, so hashes should be ordered from the preferred one to the least
+/// preferred. Returns the indices of every group with more than one member.
+pub fn clusters(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    if max_distance >= u64::BITS {
+        return match hashes.len() {
+            0 | 1 => Vec::new(),
+            v => vec![(0..v).collect()],
+        };
+    }
+    // Hashes at most `max_distance` bits apart are equal in at least one of `max_distance + 1`
+    // blocks of bits, so only groups that share a block with a hash have to be compared to it.
+    let blocks = max_distance as usize + 1;
+    let block = |hash: u64, i: usize| {
+        let (start, end) = (i * 64 / blocks, (i + 1) * 64 / blocks);
+        (i, (hash >> start) & (u64::MAX >> (64 - (end - start))))
+    };
+    let mut groups: Vec<(u64, Vec<usize>)> = Vec::new();
+    let mut by_block: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (index, hash) in hashes.iter().copied().enumerate() {
+        let fitting = (0..blocks)
+            .filter_map(|i| by_block.get(&block(hash, i)))
+            .flatten()
+            .copied()
+            .filter(|v| (groups[*v].0 ^ hash).count_ones() <= max_distance)
+            .min();
+        match fitting {
+            Some(group) => groups[group].1.push(index),
+            None => {
+                for i in 0..blocks {
+                    by_block
+                        .entry(block(hash, i))
+                        .or_default()
+                        .push(groups.len());
+                }
+                groups.push((hash, vec![index]));
+            }
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(_, members)| members)
+        .filter(|v| v.len() > 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_close_hashes() {
+        let hashes = [
+            0b0000,
+            0xFFFF_0000,
+            0b0011,
+            0xFFFF_0001,
+            0xFFFF_FFFF_0000_0000,
+        ];
+        assert_eq!(clusters(&hashes, 2), vec![vec![0, 2], vec![1, 3]]);
+        assert_eq!(clusters(&hashes, 0), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn chains_are_not_joined() {
+        // Every hash is 2 bits from the one before, but the ends are 8 bits apart.
+        let hashes = [0x00, 0x03, 0x0F, 0x3F, 0xFF];
+        assert_eq!(clusters(&hashes, 2), vec![vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn earlier_groups_are_preferred() {
+        let hashes = [0x00, 0x0F, 0x03];
+        assert_eq!(clusters(&hashes, 2), vec![vec![0, 2]]);
+        assert_eq!(clusters(&[1, 2, 3], 64), vec![vec![0, 1, 2]]);
+    }
+}
@@ -0,0 +1,339 @@
+use {
+    crate::{media_type::MediaKind, FileFingerprint},
+    image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageDecoder, ImageReader},
+    server_api::external::tokio::{fs, process::Command, task, time},
+    std::{
+        ffi::OsString,
+        io,
+        path::{Path, PathBuf},
+        process::Stdio,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+    xxhash_rust::xxh3::xxh3_128,
+};
+
+/// Thumbnail sizes if none are configured, in pixels of the longer side.
+pub const DEFAULT_SIZES: [u32; 2] = [320, 1280];
+
+const JPEG_QUALITY: u8 = 80;
+
+/// Formats the bundled decoders can read.
+const SUPPORTED_MIME_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Poster frames and previews are scaled down to fit into this size.
+const POSTER_SIZE: u32 = 1280;
+const PREVIEW_SIZE: u32 = 320;
+const PREVIEW_SECONDS: u32 = 3;
+
+/// ffmpeg runs longer than this are killed, e.g. on files that make it hang.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Creates downscaled copies of images and stills and previews of videos and keeps them in a
+/// cache directory. Videos are handled by ffmpeg, without it they get neither.
+pub struct Thumbnailer {
+    directory: PathBuf,
+    sizes: Vec<u32>,
+    ffmpeg: Option<PathBuf>,
+    previews: bool,
+}
+
+impl Thumbnailer {
+    pub fn new(
+        directory: PathBuf,
+        mut sizes: Vec<u32>,
+        ffmpeg: Option<PathBuf>,
+        previews: bool,
+    ) -> Self {
+        sizes.sort_unstable();
+        sizes.dedup();
+        Thumbnailer {
+            directory,
+            sizes,
+            ffmpeg,
+            previews,
+        }
+    }
+
+    /// The configured sizes, smallest first.
+    pub fn sizes(&self) -> &[u32] {
+        &self.sizes
+    }
+
+    pub fn supports(mime_type: &str) -> bool {
+        SUPPORTED_MIME_TYPES.contains(&mime_type)
+    }
+
+    pub fn supports_posters(&self) -> bool {
+        self.ffmpeg.is_some()
+    }
+
+    pub fn supports_previews(&self) -> bool {
+        self.ffmpeg.is_some() && self.previews
+    }
+
+    /// The thumbnail of a file in one of the configured sizes, created if it is not cached yet.
+    pub async fn thumbnail(
+        &self,
+        path: &Path,
+        encoded_path: &str,
+        size: u32,
+    ) -> Result<PathBuf, io::Error> {
+        if !self.sizes.contains(&size) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let thumbnail = self
+            .cache_path(path, encoded_path, &size.to_string(), "jpg")
+            .await?;
+        if fs::try_exists(&thumbnail).await? {
+            return Ok(thumbnail);
+        }
+        let source = path.to_path_buf();
+        let target = thumbnail.clone();
+        task::spawn_blocking(move || create_thumbnail(&source, &target, size))
+            .await
+            .map_err(io::Error::other)??;
+        Ok(thumbnail)
+    }
+
+    /// A still frame of a video, created if it is not cached yet.
+    pub async fn poster(&self, path: &Path, encoded_path: &str) -> Result<PathBuf, io::Error> {
+        let ffmpeg = self.ffmpeg.as_ref().ok_or(io::ErrorKind::Unsupported)?;
+        let poster = self.cache_path(path, encoded_path, "poster", "jpg").await?;
+        if fs::try_exists(&poster).await? {
+            return Ok(poster);
+        }
+        let scale = format!(
+            "scale='min({0},iw)':'min({0},ih)':force_original_aspect_ratio=decrease",
+            POSTER_SIZE
+        );
+        // A frame a second in is less likely to be black, but clips can be shorter than that.
+        for offset in ["1", "0"] {
+            run_ffmpeg(
+                ffmpeg,
+                path,
+                &poster,
+                &["-ss", offset],
+                &["-frames:v", "1", "-vf", &scale, "-q:v", "4", "-f", "image2"],
+            )
+            .await?;
+            if fs::try_exists(&poster).await? {
+                return Ok(poster);
+            }
+        }
+        Err(io::Error::other("ffmpeg did not produce a frame"))
+    }
+
+    /// A short, small and silent clip of the start of a video, created if it is not cached yet.
+    pub async fn preview(&self, path: &Path, encoded_path: &str) -> Result<PathBuf, io::Error> {
+        let ffmpeg = match (&self.ffmpeg, self.previews) {
+            (Some(v), true) => v,
+            _ => return Err(io::ErrorKind::Unsupported.into()),
+        };
+        let preview = self
+            .cache_path(path, encoded_path, "preview", "mp4")
+            .await?;
+        if fs::try_exists(&preview).await? {
+            return Ok(preview);
+        }
+        run_ffmpeg(
+            ffmpeg,
+            path,
+            &preview,
+            &[],
+            &[
+                "-t",
+                &PREVIEW_SECONDS.to_string(),
+                "-an",
+                "-vf",
+                &format!("scale={}:-2", PREVIEW_SIZE),
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-crf",
+                "30",
+                "-pix_fmt",
+                "yuv420p",
+                "-movflags",
+                "+faststart",
+                "-f",
+                "mp4",
+            ],
+        )
+        .await?;
+        match fs::try_exists(&preview).await? {
+            true => Ok(preview),
+            false => Err(io::Error::other("ffmpeg did not produce a preview")),
+        }
+    }
+
+    /// Creates everything that can be served for a file ahead of the first request.
+    pub async fn pregenerate(
+        &self,
+        path: &Path,
+        encoded_path: &str,
+        kind: MediaKind,
+    ) -> Result<(), io::Error> {
+        match kind {
+            MediaKind::Image => {
+                for size in self.sizes.iter() {
+                    self.thumbnail(path, encoded_path, *size).await?;
+                }
+            }
+            MediaKind::Video if self.supports_posters() => {
+                self.poster(path, encoded_path).await?;
+                if self.supports_previews() {
+                    self.preview(path, encoded_path).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Where a variant of a file is cached. The key includes the fingerprint of the original, so
+    /// edited files get new variants.
+    async fn cache_path(
+        &self,
+        path: &Path,
+        encoded_path: &str,
+        variant: &str,
+        extension: &str,
+    ) -> Result<PathBuf, io::Error> {
+        let fingerprint = FileFingerprint::from_metadata(&fs::metadata(path).await?);
+        let key = xxh3_128(
+            format!(
+                "{}\n{}\n{}\n{}",
+                encoded_path,
+                variant,
+                fingerprint.size,
+                fingerprint
+                    .modified
+                    .timestamp_nanos_opt()
+                    .unwrap_or_default()
+            )
+            .as_bytes(),
+        );
+        fs::create_dir_all(&self.directory).await?;
+        Ok(self
+            .directory
+            .join(format!("{:032x}-{}.{}", key, variant, extension)))
+    }
+}
+
+/// The ffmpeg to use. A configured binary has to work, the default one is only used if it exists.
+pub fn find_ffmpeg(configured: Option<&Path>) -> Result<Option<PathBuf>, io::Error> {
+    let ffmpeg = configured.unwrap_or(Path::new("ffmpeg"));
+    match std::process::Command::new(ffmpeg)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(v) if v.success() => Ok(Some(ffmpeg.to_path_buf())),
+        Ok(v) if configured.is_some() => {
+            Err(io::Error::other(format!("{:?} exited with {}", ffmpeg, v)))
+        }
+        Err(e) if configured.is_some() => Err(e),
+        _ => Ok(None),
+    }
+}
+
+/// Runs ffmpeg on `input`, for at most [`FFMPEG_TIMEOUT`]. The output is written next to the target
+/// and renamed, so concurrent requests never see a partial file.
+async fn run_ffmpeg(
+    ffmpeg: &Path,
+    input: &Path,
+    target: &Path,
+    input_options: &[&str],
+    output_options: &[&str],
+) -> Result<(), io::Error> {
+    let partial = partial_path(target);
+    // ffmpeg treats arguments with a `:` as protocol URLs, `file:` makes sure a path stays a path.
+    let mut input_argument = OsString::from("file:");
+    input_argument.push(input.as_os_str());
+    let mut command = Command::new(ffmpeg);
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y"])
+        .args(input_options)
+        .arg("-i")
+        .arg(input_argument)
+        .args(output_options)
+        .arg(&partial)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // The child is killed when its output future is dropped on timeout.
+    let output = match time::timeout(FFMPEG_TIMEOUT, command.output()).await {
+        Ok(v) => v?,
+        Err(_) => {
+            let _ = fs::remove_file(&partial).await;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("ffmpeg did not finish within {:?}", FFMPEG_TIMEOUT),
+            ));
+        }
+    };
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial).await;
+        return Err(io::Error::other(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    match fs::metadata(&partial).await {
+        Ok(v) if v.len() > 0 => fs::rename(&partial, target).await,
+        _ => {
+            let _ = fs::remove_file(&partial).await;
+            Ok(())
+        }
+    }
+}
+
+/// Decodes an image and turns it upright according to its EXIF orientation.
+pub fn open_image(path: &Path) -> Result<DynamicImage, io::Error> {
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(io::Error::other)?;
+    let orientation = decoder.orientation().map_err(io::Error::other)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(io::Error::other)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn create_thumbnail(source: &Path, target: &Path, size: u32) -> Result<(), io::Error> {
+    let mut image = open_image(source)?;
+    if image.width() > size || image.height() > size {
+        image = image.thumbnail(size, size);
+    }
+
+    // Written next to the target and renamed, so concurrent requests never see a partial file.
+    let partial = partial_path(target);
+    let written = std::fs::File::create(&partial).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        JpegEncoder::new_with_quality(&mut writer, JPEG_QUALITY)
+            .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))
+            .map_err(io::Error::other)?;
+        writer.into_inner().map_err(|e| e.into_error())?;
+        std::fs::rename(&partial, target)
+    });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    written
+}
+
+/// A file next to `target` that no other writer uses, as the same thumbnail can be requested and
+/// pregenerated at the same time.
+fn partial_path(target: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut name = target.as_os_str().to_os_string();
+    name.push(format!(
+        ".{}-{}.partial",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(name)
+}
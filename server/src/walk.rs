@@ -0,0 +1,151 @@
+use {
+    crate::{paths, scan_filter, sidecar, FileFingerprint, ScanErrors, ScanFilter, SidecarMode},
+    ignore::gitignore::Gitignore,
+    server_api::external::{
+        futures::{stream::FuturesUnordered, StreamExt},
+        tokio::{sync::mpsc, task},
+        types::external::chrono::{DateTime, Utc},
+    },
+    std::{
+        collections::HashMap,
+        fs::{self, FileType, Metadata},
+        io,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+/// A candidate media file found while walking, with its encoded path.
+pub type WalkedFile = (String, FileFingerprint);
+
+/// One entry of a directory listing. Only regular files come with their metadata.
+struct ListedEntry {
+    path: PathBuf,
+    file_type: FileType,
+    metadata: Option<io::Result<Metadata>>,
+}
+
+struct Listing {
+    entries: Vec<ListedEntry>,
+    /// The `.mediaignore` file of the directory.
+    ignore: Option<Arc<Gitignore>>,
+    errors: ScanErrors,
+}
+
+/// Walks a directory tree, reading up to `workers` directories at the same time, and sends every
+/// candidate file to `files` as soon as its directory was read. Entries that can not be read are
+/// collected in `errors` instead of aborting the walk. Stops early if `files` is closed.
+pub async fn walk_directory(
+    root: &Path,
+    filter: &ScanFilter,
+    ignores: &[Arc<Gitignore>],
+    workers: usize,
+    files: mpsc::Sender<WalkedFile>,
+    errors: &mut ScanErrors,
+) {
+    let mut pending = vec![(root.to_path_buf(), ignores.to_vec())];
+    let mut running = FuturesUnordered::new();
+    loop {
+        while running.len() < workers.max(1) {
+            let Some((directory, ignores)) = pending.pop() else {
+                break;
+            };
+            running.push(async move { (ignores, list_directory(directory).await) });
+        }
+        let Some((mut ignores, listing)) = running.next().await else {
+            break;
+        };
+        ignores.extend(listing.ignore);
+        errors.extend(listing.errors);
+        let read_sidecars = filter.sidecar_mode() != SidecarMode::Ignore;
+        let mut sidecars = HashMap::new();
+        let mut found = Vec::new();
+        for entry in listing.entries {
+            // Sidecars belong to their media, so they count even if the globs do not include them.
+            if read_sidecars && entry.file_type.is_file() && sidecar::is_sidecar(&entry.path) {
+                if let (Some(name), Some(Ok(modified))) = (
+                    entry.path.file_name(),
+                    entry.metadata.map(|v| v.and_then(|v| v.modified())),
+                ) {
+                    sidecars.insert(name.to_os_string(), DateTime::<Utc>::from(modified));
+                }
+                continue;
+            }
+            if filter.is_excluded(&entry.path, entry.file_type.is_dir(), &ignores) {
+                continue;
+            }
+            if entry.file_type.is_dir() {
+                pending.push((entry.path, ignores.clone()));
+            } else if entry.file_type.is_file() && filter.is_candidate(&entry.path) {
+                match entry.metadata {
+                    Some(Ok(metadata)) => {
+                        found.push((entry.path, FileFingerprint::from_metadata(&metadata)))
+                    }
+                    Some(Err(e)) => errors.push((entry.path, e)),
+                    None => {}
+                }
+            }
+        }
+        for (path, mut fingerprint) in found {
+            if let Some(name) = path.file_name() {
+                fingerprint.sidecar_modified = sidecar::latest_modified(name, &sidecars);
+            }
+            if files
+                .send((paths::encode_path(&path), fingerprint))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Lists a directory and loads its ignore file on a blocking thread, which is a lot cheaper than an
+/// asynchronous call for every entry.
+async fn list_directory(directory: PathBuf) -> Listing {
+    let directory_of_error = directory.clone();
+    task::spawn_blocking(move || {
+        let mut listing = Listing {
+            entries: Vec::new(),
+            ignore: scan_filter::load_ignore_file(&directory),
+            errors: ScanErrors::new(),
+        };
+        let entries = match fs::read_dir(&directory) {
+            Ok(v) => v,
+            Err(e) => {
+                listing.errors.push((directory, e));
+                return listing;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(v) => v,
+                Err(e) => {
+                    // The rest of the directory can not be listed, treat all of it as unreadable.
+                    listing.errors.push((directory, e));
+                    break;
+                }
+            };
+            let file_type = match entry.file_type() {
+                Ok(v) => v,
+                Err(e) => {
+                    listing.errors.push((entry.path(), e));
+                    continue;
+                }
+            };
+            listing.entries.push(ListedEntry {
+                path: entry.path(),
+                metadata: file_type.is_file().then(|| entry.metadata()),
+                file_type,
+            });
+        }
+        listing
+    })
+    .await
+    .unwrap_or_else(|e| Listing {
+        entries: Vec::new(),
+        ignore: None,
+        errors: vec![(directory_of_error, io::Error::other(e))],
+    })
+}
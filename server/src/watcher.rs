@@ -0,0 +1,47 @@
+use {
+    notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher},
+    std::{
+        collections::{HashMap, HashSet},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Changed paths per location name, filled by the watchers and drained by the request loop.
+pub type WatchQueue = Arc<Mutex<HashMap<String, HashSet<PathBuf>>>>;
+
+pub fn watch_location(
+    name: String,
+    location: &Path,
+    queue: WatchQueue,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!(
+                    "The Media Scan plugin received a watcher error for {}: {}",
+                    name, e
+                );
+                return;
+            }
+        };
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            queue
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(name.clone())
+                .or_default()
+                .extend(event.paths);
+        }
+    })?;
+    watcher.watch(location, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+pub fn drain(queue: &WatchQueue) -> HashMap<String, HashSet<PathBuf>> {
+    std::mem::take(&mut *queue.lock().unwrap_or_else(|e| e.into_inner()))
+}